@@ -26,12 +26,113 @@ fn single_galaxy() {
 
         if i % 100 == 0 {
             println!("Saving particles");
+            milkyway.update_potentials();
             milkyway.save_to_file(format!("./result/particles/particles{:05}.bin", i).as_str());
         }
         println!();
     }
 }
 
+// One year of the Earth around the Sun, as in the Python prototype, saved as a vector image.
+fn solar_system_svg() {
+    let settings = simulation::WorldSettings {
+        gravity_strength: 6.67e-11,
+        softening_length: 0.0,
+        accuracy: 0.5,
+        quadtree: false,
+        multiprocessing: false,
+    };
+
+    let mut world = simulation::World::new(settings);
+    world.add_particle(simulation::Particle {
+        mass: 1.98e30,
+        position: simulation::Vector2 { x: 0.0, y: 0.0 },
+        z: 0.0,
+        velocity: simulation::Vector2 { x: 0.0, y: 0.0 },
+        color: (1.0, 0.8, 0.2),
+        tag: 0,
+        potential: 0.0,
+    });
+    world.add_particle(simulation::Particle {
+        mass: 5.972e24,
+        position: simulation::Vector2 { x: 1.5e11, y: 0.0 },
+        z: 0.0,
+        velocity: simulation::Vector2 { x: 0.0, y: 2.978e4 },
+        color: (0.3, 0.5, 1.0),
+        tag: 0,
+        potential: 0.0,
+    });
+
+    let mut svg = simulation::SvgWriter::new(500, 500);
+    svg.particle_size = 1e-5;
+    svg.min_radius = 3.0;
+    let camera = simulation::Camera {
+        position: simulation::Vector2 { x: 0.0, y: 0.0 },
+        zoom: -38.0,
+        brightness: 1.0,
+    };
+
+    for _ in 0..365 * 24 {
+        svg.record_orbits(&world);
+        world.update(3600.0);
+    }
+    svg.record_orbits(&world);
+    svg.save(&world, &camera, "./result/tests/solar_system.svg");
+}
+
+// Compares the saved Milky Way with the density functions it was sampled from.
+fn profile_milkyway() {
+    let settings = simulation::WorldSettings {
+        gravity_strength: 1.30128e-12, //1e20 aar
+        softening_length: 0.1,
+        accuracy: 0.5,
+        quadtree: true,
+        multiprocessing: true,
+    };
+
+    let mut milkyway = simulation::World::new(settings);
+    milkyway.load_from_file("./result/tests/milkyway.bin");
+
+    let profile_settings = simulation::ProfileSettings {
+        binning: simulation::Binning::Logarithmic { inner: 0.1 },
+        ..Default::default()
+    };
+    // Everything but the central black hole.
+    let mut profile = milkyway.radial_profile(
+        milkyway.center_of_mass(),
+        &profile_settings,
+        |particle| particle.tag != 6,
+    );
+    profile.set_input(simulation::milkyway_density, 0.5);
+    profile.print();
+    profile.save_csv("./result/tests/milkyway_profile.csv");
+}
+
+// A Hernquist halo in equilibrium under the planar forces of `World`, rendered and saved for runs
+// that add it to a galaxy. `from_spherical_model` would give the 3D equilibrium, which collapses.
+fn hernquist_halo() {
+    let settings = simulation::WorldSettings {
+        gravity_strength: 1.30128e-12, //1e20 aar
+        softening_length: 0.1,
+        accuracy: 0.5,
+        quadtree: true,
+        multiprocessing: true,
+    };
+
+    let model = simulation::SphericalModel::Hernquist { scale_radius: 10.0 };
+    let halo = simulation::from_spherical_model_planar(model, 100000, 1e12, settings);
+
+    let mut renderer = simulation::Renderer::new(1024, 1024);
+    let camera = simulation::Camera {
+        position: simulation::Vector2 { x: 0.0, y: 0.0 },
+        zoom: -6.0,
+        brightness: 0.3,
+    };
+    renderer.render(&halo, &camera, "./result/tests/hernquist_halo.png");
+
+    halo.save_to_file("./result/tests/hernquist_halo.bin");
+}
+
 fn galaxy_collision() {
     let settings = simulation::WorldSettings {
         gravity_strength: 1.30128e-12, //1e20 aar
@@ -72,6 +173,7 @@ fn galaxy_collision() {
 
         if i % 100 == 0 {
             println!("Saving particles");
+            world.update_potentials();
             world.save_to_file(format!("./result/particles/particles{:05}.bin", i).as_str());
         }
         println!();
@@ -105,12 +207,159 @@ fn continue_galaxy_collision() {
 
         if i % 100 == 0 {
             println!("Saving particles");
+            world.update_potentials();
             world.save_to_file(format!("./result/particles/particles{:05}.bin", i).as_str());
         }
         println!();
     }
 }
 
+// Runs the collision over SSH with the galaxies drawn in the terminal instead of to PNGs.
+fn monitor_galaxy_collision() {
+    let settings = simulation::WorldSettings {
+        gravity_strength: 1.30128e-12, //1e20 aar
+        softening_length: 0.1,
+        accuracy: 0.5,
+        quadtree: true,
+        multiprocessing: true,
+    };
+
+    let mut world = simulation::World::new(settings);
+    world.load_from_file("./result/particles/particles02300.bin");
+
+    let mut terminal = simulation::TerminalRenderer::new(
+        160,
+        50,
+        simulation::TerminalMode::Braille,
+        simulation::RenderSettings::default(),
+    )
+    .refresh_interval(10);
+    let camera = simulation::Camera {
+        position: simulation::Vector2 { x: 0.0, y: 0.0 },
+        zoom: -9.0,
+        brightness: 0.3,
+    };
+
+    loop {
+        terminal.step(&world, camera);
+        world.update(4.0);
+    }
+}
+
+// Continues the collision while tracing the two heaviest particles, drawing their paths into the
+// frames.
+fn trace_galaxy_collision() {
+    let settings = simulation::WorldSettings {
+        gravity_strength: 1.30128e-12, //1e20 aar
+        softening_length: 0.1,
+        accuracy: 0.5,
+        quadtree: true,
+        multiprocessing: true,
+    };
+
+    let mut world = simulation::World::new(settings);
+    world.load_from_file("./result/particles/particles02300.bin");
+
+    let mut indices: Vec<usize> = (0..world.particles.len()).collect();
+    indices.sort_by(|&a, &b| world.particles[b].mass.total_cmp(&world.particles[a].mass));
+    let mut tracer = simulation::Tracer::new(&indices[..2]);
+
+    let mut renderer = simulation::Renderer::new(1024, 1024);
+    let camera = simulation::Camera {
+        position: simulation::Vector2 { x: 0.0, y: 0.0 },
+        zoom: -6.0,
+        brightness: 0.3,
+    };
+
+    tracer.record(&world);
+    for i in 2301..3000 {
+        world.update(4.0);
+        tracer.record(&world);
+        renderer.render_frame(&world, &camera);
+        tracer.draw(&mut renderer, camera, 2);
+        renderer.save(format!("./result/frames/{:05}.png", i).as_str());
+
+        if i % 100 == 0 {
+            tracer.save_csv("./result/tests/traces.csv");
+            tracer.save_to_file("./result/tests/traces.bin");
+        }
+    }
+}
+
+// Lists the clumps left after the collision and renders the particles colored by group.
+fn groups_galaxy_collision() {
+    let settings = simulation::WorldSettings {
+        gravity_strength: 1.30128e-12, //1e20 aar
+        softening_length: 0.1,
+        accuracy: 0.5,
+        quadtree: true,
+        multiprocessing: true,
+    };
+
+    let mut world = simulation::World::new(settings);
+    world.load_from_file("./result/particles/particles02700.bin");
+
+    let catalog = world.find_groups(&simulation::FofSettings {
+        linking_length: 0.2,
+        min_members: 20,
+    });
+    for (g, group) in catalog.groups.iter().enumerate().take(10) {
+        println!(
+            "group {}: {} particles, {:e} solar masses at {}",
+            g,
+            group.members.len(),
+            group.mass,
+            group.center
+        );
+    }
+    catalog.save_csv("./result/tests/collision_groups.csv");
+
+    let colors = [(1.0, 0.3, 0.3), (0.3, 1.0, 0.3), (0.3, 0.3, 1.0), (1.0, 1.0, 0.3)];
+    for (particle, group) in world.particles.iter_mut().zip(&catalog.membership) {
+        particle.color = match group {
+            Some(g) => colors[g % colors.len()],
+            None => (0.3, 0.3, 0.3),
+        };
+    }
+    let mut renderer = simulation::Renderer::new(1024, 1024);
+    let camera = simulation::Camera {
+        position: simulation::Vector2 { x: 0.0, y: 0.0 },
+        zoom: -6.0,
+        brightness: 0.3,
+    };
+    renderer.render(&world, &camera, "./result/tests/collision_groups.png");
+}
+
+// Continues the collision and records how much of each galaxy is still bound. The first half of
+// the particles came from the first galaxy.
+fn bound_mass_galaxy_collision() {
+    let settings = simulation::WorldSettings {
+        gravity_strength: 1.30128e-12, //1e20 aar
+        softening_length: 0.1,
+        accuracy: 0.5,
+        quadtree: true,
+        multiprocessing: true,
+    };
+
+    let mut world = simulation::World::new(settings);
+    world.load_from_file("./result/particles/particles02300.bin");
+
+    let half = world.particles.len() / 2;
+    let mut history = simulation::BoundMassHistory::new()
+        .track("galaxy 1", (0..half).collect())
+        .track("galaxy 2", (half..world.particles.len()).collect());
+
+    for i in 0..700 {
+        if i % 10 == 0 {
+            history.record(&world);
+            let (time, masses) = history.rows.last().unwrap();
+            println!("t = {}: bound masses {:e} and {:e}", time, masses[0], masses[1]);
+            history.save_csv("./result/tests/collision_bound_mass.csv");
+        }
+        world.update(4.0);
+    }
+}
+
 fn last_image_galaxy_collision() {
     let settings = simulation::WorldSettings {
         gravity_strength: 1.30128e-12, //1e20 aar
@@ -123,7 +372,11 @@ fn last_image_galaxy_collision() {
     let mut world = simulation::World::new(settings);
     world.load_from_file("./result/particles/particles02700.bin");
 
-    let mut renderer = simulation::Renderer::new(3840, 2160);
+    let render_settings = simulation::RenderSettings {
+        multiprocessing: true,
+        ..Default::default()
+    };
+    let mut renderer = simulation::Renderer::with_settings(3840, 2160, render_settings);
     let camera = simulation::Camera {
         position: simulation::Vector2 { x: 0.0, y: 0.0 },
         zoom: -9.5,
@@ -131,4 +384,133 @@ fn last_image_galaxy_collision() {
     };
 
     renderer.render(&world, &camera, "result/tests/collision_after.png");
-}
\ No newline at end of file
+}
+
+fn poster_galaxy_collision() {
+    let settings = simulation::WorldSettings {
+        gravity_strength: 1.30128e-12, //1e20 aar
+        softening_length: 0.1,
+        accuracy: 0.5,
+        quadtree: true,
+        multiprocessing: true,
+    };
+
+    let mut world = simulation::World::new(settings);
+    world.load_from_file("./result/particles/particles02700.bin");
+
+    let render_settings = simulation::RenderSettings {
+        supersampling: 2,
+        downsample_filter: simulation::DownsampleFilter::Tent,
+        multiprocessing: true,
+        ..Default::default()
+    };
+    let mut renderer = simulation::Renderer::with_settings(15360, 8640, render_settings);
+    let camera = simulation::Camera {
+        position: simulation::Vector2 { x: 0.0, y: 0.0 },
+        zoom: -11.5,
+        brightness: 0.3,
+    };
+
+    renderer.render_tiled(&world, &camera.into(), 2048);
+    renderer.save("result/tests/collision_poster.png");
+}
+
+fn rerender_galaxy_collision() {
+    let settings = simulation::WorldSettings {
+        gravity_strength: 1.30128e-12, //1e20 aar
+        softening_length: 0.1,
+        accuracy: 0.5,
+        quadtree: true,
+        multiprocessing: false,
+    };
+
+    simulation::render_snapshots(
+        "./result/particles",
+        "./result/frames",
+        &settings,
+        1024,
+        1024,
+        &simulation::RenderSettings::default(),
+        |_, _| simulation::Camera {
+            position: simulation::Vector2 { x: 0.0, y: 0.0 },
+            zoom: -6.0,
+            brightness: 0.3,
+        },
+    );
+}
+
+fn flyby_galaxy_collision() {
+    let settings = simulation::WorldSettings {
+        gravity_strength: 1.30128e-12, //1e20 aar
+        softening_length: 0.1,
+        accuracy: 0.5,
+        quadtree: true,
+        multiprocessing: false,
+    };
+    let camera_path = simulation::CameraPath::load_from_file("./result/camera.txt");
+
+    simulation::render_snapshots(
+        "./result/particles",
+        "./result/frames",
+        &settings,
+        1024,
+        1024,
+        &simulation::RenderSettings::default(),
+        |i, world| camera_path.camera_at(i as f64, world),
+    );
+}
+
+fn video_galaxy_collision() {
+    let settings = simulation::WorldSettings {
+        gravity_strength: 1.30128e-12, //1e20 aar
+        softening_length: 0.1,
+        accuracy: 0.5,
+        quadtree: true,
+        multiprocessing: false,
+    };
+
+    let mut world = simulation::World::new(settings);
+    let mut renderer = simulation::Renderer::new(1024, 1024);
+    let camera = simulation::Camera {
+        position: simulation::Vector2 { x: 0.0, y: 0.0 },
+        zoom: -6.0,
+        brightness: 0.3,
+    };
+    let mut video = simulation::VideoWriter::new("./result/collision.avi", 1024, 1024, 30);
+
+    let files = simulation::snapshot_files("./result/particles");
+    for (i, file) in files.iter().enumerate() {
+        world.load_from_file(file.to_str().unwrap());
+        renderer.render_frame(&world, &camera);
+        video.write_frame(renderer.image());
+        println!("{}/{} frames completed", i + 1, files.len());
+    }
+    video.finish();
+}
+
+fn panels_galaxy_collision() {
+    let settings = simulation::WorldSettings {
+        gravity_strength: 1.30128e-12, //1e20 aar
+        softening_length: 0.1,
+        accuracy: 0.5,
+        quadtree: true,
+        multiprocessing: false,
+    };
+    let camera = simulation::Camera {
+        position: simulation::Vector2 { x: 0.0, y: 0.0 },
+        zoom: -6.0,
+        brightness: 0.3,
+    };
+
+    let mut world = simulation::World::new(settings);
+    let mut compositor =
+        simulation::Compositor::new(3, 2, 512, 512, simulation::RenderSettings::default())
+            .border(4, (1.0, 1.0, 1.0));
+    let files = simulation::snapshot_files("./result/particles");
+    for i in 0..6 {
+        let j = i * (files.len() - 1) / 5;
+        world.load_from_file(files[j].to_str().unwrap());
+        compositor.render_panel(i as u32, &world, camera, &format!("SNAPSHOT {}", j));
+    }
+    compositor.save("./result/tests/collision_panels.png");
+}
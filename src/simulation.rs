@@ -1,11 +1,43 @@
+pub mod camera;
+pub mod colormap;
+pub mod compositor;
+pub mod equilibrium;
 pub mod galaxy;
+pub mod groups;
+pub mod kdtree;
+pub mod overlay;
 pub mod particle;
+pub mod pipeline;
+pub mod profile;
+pub mod quantity;
 pub mod renderer;
+pub mod spherical;
+pub mod svg;
+pub mod terminal;
+pub mod tracer;
+pub mod unbinding;
 pub mod vector;
+pub mod video;
 pub mod world;
 
+pub use camera::*;
+pub use colormap::*;
+pub use compositor::*;
+pub use equilibrium::*;
 pub use galaxy::*;
+pub use groups::*;
+pub use kdtree::*;
+pub use overlay::*;
 pub use particle::*;
+pub use pipeline::*;
+pub use profile::*;
+pub use quantity::*;
 pub use renderer::*;
+pub use spherical::*;
+pub use svg::*;
+pub use terminal::*;
+pub use tracer::*;
+pub use unbinding::*;
 pub use vector::*;
+pub use video::*;
 pub use world::*;
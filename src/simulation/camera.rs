@@ -0,0 +1,292 @@
+use super::*;
+use std::fs;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Camera {
+    pub position: Vector2,
+    pub zoom: f64,
+    pub brightness: f64,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Projection {
+    // Parallel projection, with the view width set by the zoom like the 2D camera.
+    Orthographic,
+    // Horizontal field of view in radians. The zoom is not used.
+    Perspective { fov: f64 },
+}
+
+// Dims particles by their depth in front of the camera.
+#[derive(Clone, Copy, Debug)]
+pub enum DepthCue {
+    None,
+    // Full brightness up to `near`, fading linearly to nothing at `far`.
+    Fog { near: f64, far: f64 },
+    // Brightness falls off as exp(-depth / length).
+    Attenuation { length: f64 },
+}
+
+impl DepthCue {
+    pub fn weight(&self, depth: f64) -> f64 {
+        match self {
+            DepthCue::None => 1.0,
+            DepthCue::Fog { near, far } => (1.0 - (depth - near) / (far - near)).clamp(0.0, 1.0),
+            DepthCue::Attenuation { length } => (-depth / length).exp().min(1.0),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Camera3D {
+    pub position: Vector3,
+    // Need not be normalised, and `up` need not be perpendicular to `direction`.
+    pub direction: Vector3,
+    pub up: Vector3,
+    pub projection: Projection,
+    pub zoom: f64,
+    pub brightness: f64,
+    pub depth_cue: DepthCue,
+}
+
+impl Camera3D {
+    // Orthographic camera looking at `center` from `inclination` radians away from face on, so 0
+    // is the same view as the 2D camera and PI / 2 is edge on. The disc stays upright on screen.
+    pub fn inclined(center: Vector2, inclination: f64, zoom: f64, brightness: f64) -> Self {
+        let (sin, cos) = inclination.sin_cos();
+        let direction = Vector3 {
+            x: 0.0,
+            y: sin,
+            z: -cos,
+        };
+        Self {
+            position: Vector3::from(center) - direction,
+            direction,
+            up: Vector3 {
+                x: 0.0,
+                y: cos,
+                z: sin,
+            },
+            projection: Projection::Orthographic,
+            zoom,
+            brightness,
+            depth_cue: DepthCue::None,
+        }
+    }
+
+    // Right, up and forward unit vectors of the view.
+    pub fn basis(&self) -> (Vector3, Vector3, Vector3) {
+        let forward = self.direction.normalize();
+        let right = forward.cross(self.up).normalize();
+        let up = right.cross(forward);
+        (right, up, forward)
+    }
+}
+
+impl From<Camera> for Camera3D {
+    // The top down view of the 2D camera.
+    fn from(camera: Camera) -> Self {
+        Camera3D::inclined(camera.position, 0.0, camera.zoom, camera.brightness)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct CameraKeyframe {
+    pub time: f64,
+    pub camera: Camera,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interpolation {
+    Linear,
+    // Uniform Catmull-Rom spline through the keyframe positions.
+    CatmullRom,
+}
+
+// Zoom is already the log2 of the magnification, so interpolating it is interpolating in log space.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ZoomEasing {
+    Linear,
+    // Smoothstep between keyframes, so zooms start and stop gently.
+    Smooth,
+}
+
+// What the keyframe positions are relative to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CameraTarget {
+    Fixed,
+    CenterOfMass,
+    // Index of the particle to follow.
+    Particle(usize),
+    // Centre of mass of the particles with the tag, e.g. a central black hole.
+    Tag(u32),
+}
+
+#[derive(Clone, Debug)]
+pub struct CameraPath {
+    pub keyframes: Vec<CameraKeyframe>,
+    pub interpolation: Interpolation,
+    pub zoom_easing: ZoomEasing,
+    pub target: CameraTarget,
+}
+
+impl CameraPath {
+    pub fn new(keyframes: Vec<CameraKeyframe>) -> Self {
+        Self {
+            keyframes,
+            interpolation: Interpolation::Linear,
+            zoom_easing: ZoomEasing::Linear,
+            target: CameraTarget::Fixed,
+        }
+    }
+
+    pub fn camera_at(&self, time: f64, world: &World) -> Camera {
+        let mut camera = self.interpolate(time);
+        camera.position += self.target_position(world);
+        camera
+    }
+
+    fn interpolate(&self, time: f64) -> Camera {
+        let keyframes = &self.keyframes;
+        let next = keyframes.partition_point(|keyframe| keyframe.time <= time);
+        if next == 0 {
+            return keyframes[0].camera;
+        }
+        if next == keyframes.len() {
+            return keyframes[keyframes.len() - 1].camera;
+        }
+
+        let from = keyframes[next - 1];
+        let to = keyframes[next];
+        let t = (time - from.time) / (to.time - from.time);
+
+        let position = match self.interpolation {
+            Interpolation::Linear => {
+                from.camera.position + (to.camera.position - from.camera.position) * t
+            }
+            Interpolation::CatmullRom => {
+                let before = keyframes[next.saturating_sub(2)].camera.position;
+                let after = keyframes[(next + 1).min(keyframes.len() - 1)]
+                    .camera
+                    .position;
+                catmull_rom(before, from.camera.position, to.camera.position, after, t)
+            }
+        };
+        let zoom_t = match self.zoom_easing {
+            ZoomEasing::Linear => t,
+            ZoomEasing::Smooth => t * t * (3.0 - 2.0 * t),
+        };
+
+        Camera {
+            position,
+            zoom: from.camera.zoom + (to.camera.zoom - from.camera.zoom) * zoom_t,
+            brightness: from.camera.brightness
+                + (to.camera.brightness - from.camera.brightness) * t,
+        }
+    }
+
+    fn target_position(&self, world: &World) -> Vector2 {
+        match self.target {
+            CameraTarget::Fixed => Vector2 { x: 0.0, y: 0.0 },
+            CameraTarget::CenterOfMass => world.center_of_mass(),
+            CameraTarget::Particle(index) => world.particles[index].position,
+            CameraTarget::Tag(tag) => {
+                let mut position = Vector2 { x: 0.0, y: 0.0 };
+                let mut mass = 0.0;
+                for particle in world
+                    .particles
+                    .iter()
+                    .filter(|particle| particle.tag == tag)
+                {
+                    position += particle.position * particle.mass;
+                    mass += particle.mass;
+                }
+                if mass > 0.0 {
+                    position / mass
+                } else {
+                    position
+                }
+            }
+        }
+    }
+
+    // Reads a camera path from a text file with one keyframe per line as
+    // `time x y zoom brightness`, optionally preceded by the settings
+    // `interpolation linear|catmull-rom`, `zoom-easing linear|smooth` and
+    // `target fixed|com|particle <index>|tag <tag>`. Everything after a `#` is ignored.
+    pub fn load_from_file(path: &str) -> Self {
+        let text = fs::read_to_string(path).unwrap();
+        let mut camera_path = Self::new(vec![]);
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap();
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words.as_slice() {
+                [] => {}
+                ["interpolation", "linear"] => camera_path.interpolation = Interpolation::Linear,
+                ["interpolation", "catmull-rom"] => {
+                    camera_path.interpolation = Interpolation::CatmullRom
+                }
+                ["zoom-easing", "linear"] => camera_path.zoom_easing = ZoomEasing::Linear,
+                ["zoom-easing", "smooth"] => camera_path.zoom_easing = ZoomEasing::Smooth,
+                ["target", "fixed"] => camera_path.target = CameraTarget::Fixed,
+                ["target", "com"] => camera_path.target = CameraTarget::CenterOfMass,
+                ["target", "particle", index] => {
+                    camera_path.target = CameraTarget::Particle(
+                        index
+                            .parse()
+                            .unwrap_or_else(|_| invalid_line(path, number, line)),
+                    )
+                }
+                ["target", "tag", tag] => {
+                    camera_path.target = CameraTarget::Tag(
+                        tag.parse()
+                            .unwrap_or_else(|_| invalid_line(path, number, line)),
+                    )
+                }
+                [time, x, y, zoom, brightness] => {
+                    let number = |word: &str| {
+                        word.parse::<f64>()
+                            .unwrap_or_else(|_| invalid_line(path, number, line))
+                    };
+                    camera_path.keyframes.push(CameraKeyframe {
+                        time: number(time),
+                        camera: Camera {
+                            position: Vector2 {
+                                x: number(x),
+                                y: number(y),
+                            },
+                            zoom: number(zoom),
+                            brightness: number(brightness),
+                        },
+                    });
+                }
+                _ => invalid_line(path, number, line),
+            }
+        }
+        if camera_path.keyframes.is_empty() {
+            panic!("{}: camera path has no keyframes", path);
+        }
+        camera_path
+            .keyframes
+            .sort_by(|a, b| a.time.total_cmp(&b.time));
+        camera_path
+    }
+}
+
+fn invalid_line(path: &str, number: usize, line: &str) -> ! {
+    panic!(
+        "{}:{}: invalid camera path line '{}'",
+        path,
+        number + 1,
+        line
+    );
+}
+
+fn catmull_rom(p0: Vector2, p1: Vector2, p2: Vector2, p3: Vector2, t: f64) -> Vector2 {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    (p1 * 2.0
+        + (p2 - p0) * t
+        + (p0 * 2.0 - p1 * 5.0 + p2 * 4.0 - p3) * t2
+        + (p1 * 3.0 - p0 - p2 * 3.0 + p3) * t3)
+        * 0.5
+}
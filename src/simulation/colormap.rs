@@ -0,0 +1,126 @@
+#[derive(Clone, Copy, Debug)]
+pub enum ToneMapper {
+    Linear,
+    // log2(value + 1), the original renderer curve.
+    Log,
+    // asinh(value / softening), linear below the softening and logarithmic above it.
+    Asinh { softening: f64 },
+    Gamma(f64),
+    Reinhard,
+    // Logarithmic, with the given percentile (0-100) of the non-empty pixels mapped to white.
+    AutoExposure { percentile: f64 },
+}
+
+impl ToneMapper {
+    // The value the mapping is normalised by, only used for auto exposure.
+    pub fn exposure(&self, values: &[f64]) -> f64 {
+        match self {
+            ToneMapper::AutoExposure { percentile } => {
+                let mut non_empty: Vec<f64> =
+                    values.iter().cloned().filter(|value| *value > 0.0).collect();
+                if non_empty.is_empty() {
+                    return 1.0;
+                }
+                let rank = (percentile / 100.0 * (non_empty.len() - 1) as f64).round() as usize;
+                let rank = rank.min(non_empty.len() - 1);
+                let (_, value, _) = non_empty.select_nth_unstable_by(rank, |a, b| a.total_cmp(b));
+                *value
+            }
+            _ => 1.0,
+        }
+    }
+
+    // Maps an accumulated value to [0, 1].
+    pub fn map(&self, value: f64, brightness: f64, exposure: f64) -> f64 {
+        let mapped = match self {
+            ToneMapper::Linear => value * brightness,
+            ToneMapper::Log => (value + 1.0).log2() * brightness,
+            ToneMapper::Asinh { softening } => (value / softening).asinh() * brightness,
+            ToneMapper::Gamma(gamma) => (value * brightness).powf(1.0 / gamma),
+            ToneMapper::Reinhard => {
+                let scaled = value * brightness;
+                scaled / (1.0 + scaled)
+            }
+            ToneMapper::AutoExposure { .. } => {
+                (value + 1.0).log2() / (exposure + 1.0).log2() * brightness
+            }
+        };
+        if mapped.is_nan() {
+            0.0
+        } else {
+            mapped.clamp(0.0, 1.0)
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Colormap {
+    Viridis,
+    Magma,
+    Inferno,
+    // Stops as (position in [0, 1], color), sorted by position.
+    Gradient(Vec<(f64, (f64, f64, f64))>),
+}
+
+const VIRIDIS: [u32; 9] = [
+    0x440154, 0x472d7b, 0x3b528b, 0x2c728e, 0x21918c, 0x28ae80, 0x5ec962, 0xaddc30, 0xfde725,
+];
+const MAGMA: [u32; 9] = [
+    0x000004, 0x1c1044, 0x4f127b, 0x812581, 0xb5367a, 0xe55064, 0xfb8761, 0xfec287, 0xfcfdbf,
+];
+const INFERNO: [u32; 9] = [
+    0x000004, 0x1f0c48, 0x550f6d, 0x88226a, 0xba3655, 0xe35933, 0xf98c0a, 0xf9c932, 0xfcffa4,
+];
+
+impl Colormap {
+    pub fn color(&self, t: f64) -> (f64, f64, f64) {
+        let t = if t.is_nan() { 0.0 } else { t.clamp(0.0, 1.0) };
+        match self {
+            Colormap::Viridis => sample_table(&VIRIDIS, t),
+            Colormap::Magma => sample_table(&MAGMA, t),
+            Colormap::Inferno => sample_table(&INFERNO, t),
+            Colormap::Gradient(stops) => {
+                if stops.is_empty() {
+                    return (t, t, t);
+                }
+                let next = stops.partition_point(|(position, _)| *position < t);
+                if next == 0 {
+                    return stops[0].1;
+                }
+                if next == stops.len() {
+                    return stops[stops.len() - 1].1;
+                }
+                let (start, from) = stops[next - 1];
+                let (end, to) = stops[next];
+                let s = if end > start {
+                    (t - start) / (end - start)
+                } else {
+                    1.0
+                };
+                lerp_color(from, to, s)
+            }
+        }
+    }
+}
+
+fn sample_table(table: &[u32], t: f64) -> (f64, f64, f64) {
+    let position = t * (table.len() - 1) as f64;
+    let i = (position as usize).min(table.len() - 2);
+    lerp_color(hex_color(table[i]), hex_color(table[i + 1]), position - i as f64)
+}
+
+fn hex_color(hex: u32) -> (f64, f64, f64) {
+    (
+        ((hex >> 16) & 0xff) as f64 / 255.0,
+        ((hex >> 8) & 0xff) as f64 / 255.0,
+        (hex & 0xff) as f64 / 255.0,
+    )
+}
+
+fn lerp_color(from: (f64, f64, f64), to: (f64, f64, f64), t: f64) -> (f64, f64, f64) {
+    (
+        from.0 + (to.0 - from.0) * t,
+        from.1 + (to.1 - from.1) * t,
+        from.2 + (to.2 - from.2) * t,
+    )
+}
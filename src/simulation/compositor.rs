@@ -0,0 +1,93 @@
+use super::*;
+
+// Lays out several renders in a grid in one image, e.g. one moment from several cameras or a
+// sequence of snapshots. Panels are filled row by row.
+pub struct Compositor {
+    columns: u32,
+    rows: u32,
+    panel_width: u32,
+    panel_height: u32,
+    border: u32,
+    // Renders every panel, so its settings can be changed between panels.
+    pub renderer: Renderer,
+    img_buffer: image::RgbImage,
+}
+
+impl Compositor {
+    pub fn new(
+        columns: u32,
+        rows: u32,
+        panel_width: u32,
+        panel_height: u32,
+        settings: RenderSettings,
+    ) -> Self {
+        Self {
+            columns,
+            rows,
+            panel_width,
+            panel_height,
+            border: 0,
+            renderer: Renderer::with_settings(panel_width, panel_height, settings),
+            img_buffer: image::ImageBuffer::new(columns * panel_width, rows * panel_height),
+        }
+    }
+
+    // Separates the panels and surrounds the grid with `width` pixels of `color`.
+    pub fn border(mut self, width: u32, color: (f64, f64, f64)) -> Self {
+        self.border = width;
+        self.img_buffer = image::ImageBuffer::from_pixel(
+            self.columns * (self.panel_width + width) + width,
+            self.rows * (self.panel_height + width) + width,
+            image::Rgb(to_rgb(color)),
+        );
+        self
+    }
+
+    // Renders `world` into the panel at `index` with `label` in its top left corner. An empty label
+    // draws nothing.
+    pub fn render_panel<C: Into<Camera3D>>(
+        &mut self,
+        index: u32,
+        world: &World,
+        camera: C,
+        label: &str,
+    ) {
+        if index >= self.columns * self.rows {
+            panic!(
+                "Panel {} is outside the {}x{} grid",
+                index, self.columns, self.rows
+            );
+        }
+
+        // Panels are separate renders, so trails must not carry over from the previous panel.
+        self.renderer.clear_buffers();
+        self.renderer.render_frame_3d(world, &camera.into());
+        let x = self.border + (index % self.columns) * (self.panel_width + self.border);
+        let y = self.border + (index / self.columns) * (self.panel_height + self.border);
+        image::imageops::replace(
+            &mut self.img_buffer,
+            self.renderer.image(),
+            x as i64,
+            y as i64,
+        );
+
+        let scale = (self.panel_height as i64 / 360).max(1);
+        let margin = 8 * scale;
+        draw_text(
+            &mut self.img_buffer,
+            x as i64 + margin,
+            y as i64 + margin,
+            label,
+            scale,
+            [255, 255, 255],
+        );
+    }
+
+    pub fn save(&self, filepath: &str) {
+        self.img_buffer.save(filepath).unwrap();
+    }
+
+    pub fn image(&self) -> &image::RgbImage {
+        &self.img_buffer
+    }
+}
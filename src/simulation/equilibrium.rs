@@ -0,0 +1,326 @@
+use super::*;
+use rand::Rng;
+use std::f64::consts::PI;
+
+#[derive(Clone, Copy, Debug)]
+pub enum CircularSpeed {
+    // Softened point mass of everything inside the radius, cheap but ignores the flattening.
+    Enclosed,
+    // Azimuthally averaged radial acceleration from the force calculation in `WorldSettings`.
+    Tree,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Dispersion {
+    Cold,
+    // Non-rotating with equal radial and tangential dispersion, for bulges and haloes.
+    Isotropic,
+    // Rotating disc with the radial dispersion set by a constant Toomre Q.
+    ToomreQ(f64),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct EquilibriumSettings {
+    pub circular_speed: CircularSpeed,
+    pub dispersion: Dispersion,
+    pub num_bins: usize,
+}
+
+// Fewer particles per bin make the surface density, and the derivatives taken from it, too noisy.
+const MIN_BIN_PARTICLES: usize = 50;
+
+// Bins on each side of the local fits used for derivatives.
+const SLOPE_WINDOW: usize = 3;
+
+struct RadialBin {
+    radius: f64,
+    surface_density: f64,
+    circular_speed_squared: f64,
+    mean_speed: f64,
+    sigma_radial: f64,
+    sigma_tangential: f64,
+}
+
+impl World {
+    pub fn set_equilibrium_velocity<F>(&mut self, settings: &EquilibriumSettings, selection: F)
+    where
+        F: Fn(&Particle) -> bool,
+    {
+        let center = self.center_of_mass();
+        let center_velocity = self.center_of_mass_velocity();
+        self.set_equilibrium_velocity_around(center, center_velocity, settings, selection);
+    }
+
+    // Like `set_equilibrium_velocity`, but around a known centre instead of the centre of mass,
+    // which a few far out particles can pull away from the centre of a cuspy model.
+    pub(super) fn set_equilibrium_velocity_around<F>(
+        &mut self,
+        center: Vector2,
+        center_velocity: Vector2,
+        settings: &EquilibriumSettings,
+        selection: F,
+    ) where
+        F: Fn(&Particle) -> bool,
+    {
+        let mut selected: Vec<(usize, f64)> = vec![];
+        for (i, particle) in self.particles.iter().enumerate() {
+            if selection(particle) {
+                selected.push((i, (particle.position - center).abs()));
+            }
+        }
+        if selected.is_empty() {
+            return;
+        }
+        selected.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+        let accelerations = match settings.circular_speed {
+            CircularSpeed::Enclosed => vec![],
+            CircularSpeed::Tree => self
+                .calculate_forces_auto()
+                .iter()
+                .zip(&self.particles)
+                .map(|(force, particle)| *force / particle.mass)
+                .collect(),
+        };
+
+        let mut bins = self.equilibrium_bins(&selected, settings, center, &accelerations);
+        match settings.dispersion {
+            Dispersion::Cold => {
+                for bin in &mut bins {
+                    bin.mean_speed = bin.circular_speed_squared.sqrt();
+                }
+            }
+            Dispersion::Isotropic => isotropic_dispersion(&mut bins),
+            Dispersion::ToomreQ(q) => {
+                toomre_dispersion(&mut bins, q, self.settings.gravity_strength)
+            }
+        }
+
+        let mut rng = rand::thread_rng();
+        for (i, distance) in selected {
+            let particle = &mut self.particles[i];
+            if distance == 0.0 {
+                particle.velocity = center_velocity;
+                continue;
+            }
+            let radial = (particle.position - center) / distance;
+            let tangential = Vector2 {
+                x: -radial.y,
+                y: radial.x,
+            };
+
+            let (mean_speed, sigma_radial, sigma_tangential) = interpolate_bins(&bins, distance);
+            let radial_speed = sigma_radial * gaussian(&mut rng);
+            let tangential_speed = mean_speed + sigma_tangential * gaussian(&mut rng);
+
+            particle.velocity =
+                center_velocity + radial * radial_speed + tangential * tangential_speed;
+        }
+    }
+
+    // Splits the selected particles (sorted by radius) into bins with equal particle counts.
+    fn equilibrium_bins(
+        &self,
+        selected: &[(usize, f64)],
+        settings: &EquilibriumSettings,
+        center: Vector2,
+        accelerations: &[Vector2],
+    ) -> Vec<RadialBin> {
+        let mut all_radii: Vec<(f64, f64)> = self
+            .particles
+            .iter()
+            .map(|particle| ((particle.position - center).abs(), particle.mass))
+            .collect();
+        all_radii.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let mut enclosed_mass = vec![0.0];
+        for (_, mass) in &all_radii {
+            enclosed_mass.push(enclosed_mass.last().unwrap() + mass);
+        }
+
+        let num_bins = settings
+            .num_bins
+            .min(selected.len() / MIN_BIN_PARTICLES)
+            .max(1);
+        let mut bins = vec![];
+        let mut inner_edge = 0.0;
+        for b in 0..num_bins {
+            let start = b * selected.len() / num_bins;
+            let end = (b + 1) * selected.len() / num_bins;
+            let members = &selected[start..end];
+            let outer_edge = if end < selected.len() {
+                (members[members.len() - 1].1 + selected[end].1) / 2.0
+            } else {
+                members[members.len() - 1].1
+            };
+
+            let mut mass = 0.0;
+            let mut radius = 0.0;
+            let mut radial_acceleration = 0.0;
+            for (i, distance) in members {
+                let particle = &self.particles[*i];
+                mass += particle.mass;
+                radius += distance;
+                if !accelerations.is_empty() && *distance > 0.0 {
+                    let to_center = (center - particle.position) / *distance;
+                    radial_acceleration += accelerations[*i].dot(to_center) * distance;
+                }
+            }
+            radius /= members.len() as f64;
+
+            let area = PI * (outer_edge * outer_edge - inner_edge * inner_edge);
+            let surface_density = if area > 0.0 { mass / area } else { 0.0 };
+
+            let circular_speed_squared = match settings.circular_speed {
+                CircularSpeed::Enclosed => {
+                    let inside = all_radii.partition_point(|(r, _)| *r < radius);
+                    let softening = self.settings.softening_length;
+                    self.settings.gravity_strength * enclosed_mass[inside] * radius
+                        / (radius * radius + softening * softening)
+                }
+                CircularSpeed::Tree => radial_acceleration / members.len() as f64,
+            };
+
+            bins.push(RadialBin {
+                radius,
+                surface_density,
+                circular_speed_squared: circular_speed_squared.max(0.0),
+                mean_speed: 0.0,
+                sigma_radial: 0.0,
+                sigma_tangential: 0.0,
+            });
+            inner_edge = outer_edge;
+        }
+        bins
+    }
+}
+
+// Jeans equation without rotation: d(Σσ²)/dR = -Σ v_c²/R, integrated inwards from the edge.
+fn isotropic_dispersion(bins: &mut [RadialBin]) {
+    let mut pressure = 0.0;
+    for i in (0..bins.len()).rev() {
+        if i + 1 < bins.len() {
+            let inner = &bins[i];
+            let outer = &bins[i + 1];
+            pressure += (jeans_integrand(inner) + jeans_integrand(outer)) / 2.0
+                * (outer.radius - inner.radius);
+        }
+        let bin = &mut bins[i];
+        let sigma = if bin.surface_density > 0.0 {
+            (pressure / bin.surface_density).sqrt()
+        } else {
+            0.0
+        };
+        bin.mean_speed = 0.0;
+        bin.sigma_radial = sigma;
+        bin.sigma_tangential = sigma;
+    }
+}
+
+fn jeans_integrand(bin: &RadialBin) -> f64 {
+    if bin.radius > 0.0 {
+        bin.surface_density * bin.circular_speed_squared / bin.radius
+    } else {
+        0.0
+    }
+}
+
+// σ_R = Q 3.36 G Σ / κ, with σ_φ from the epicyclic approximation and the mean rotation from the
+// asymmetric drift in the radial Jeans equation.
+fn toomre_dispersion(bins: &mut [RadialBin], q: f64, gravity_strength: f64) {
+    let omega_squared: Vec<f64> = bins
+        .iter()
+        .map(|bin| bin.circular_speed_squared / (bin.radius * bin.radius))
+        .collect();
+
+    let radii: Vec<f64> = bins.iter().map(|bin| bin.radius).collect();
+    let mut kappa_squared = vec![];
+    for i in 0..bins.len() {
+        let derivative = local_slope(&radii, &omega_squared, i);
+        let kappa = bins[i].radius * derivative + 4.0 * omega_squared[i];
+        kappa_squared.push(kappa.max(0.0));
+    }
+
+    for i in 0..bins.len() {
+        let bin = &mut bins[i];
+        if bin.radius == 0.0 || kappa_squared[i] == 0.0 || omega_squared[i] == 0.0 {
+            continue;
+        }
+        // Where the disc is too heavy to reach Q with rotation alone it is kept no hotter than
+        // its circular speed.
+        bin.sigma_radial = (q * 3.36 * gravity_strength * bin.surface_density
+            / kappa_squared[i].sqrt())
+        .min(bin.circular_speed_squared.sqrt());
+        let ratio = kappa_squared[i] / (4.0 * omega_squared[i]);
+        bin.sigma_tangential = bin.sigma_radial * ratio.sqrt();
+    }
+
+    // Only bins with a pressure take part in the fit, as the log of zero would swamp it.
+    let (log_radii, log_pressure): (Vec<f64>, Vec<f64>) = bins
+        .iter()
+        .map(|bin| {
+            let pressure = bin.surface_density * bin.sigma_radial * bin.sigma_radial;
+            (bin.radius.ln(), pressure.ln())
+        })
+        .filter(|(log_radius, log_pressure)| log_radius.is_finite() && log_pressure.is_finite())
+        .unzip();
+    for bin in bins.iter_mut() {
+        let sigma_squared = bin.sigma_radial * bin.sigma_radial;
+        let mut mean_speed_squared = bin.circular_speed_squared;
+        if sigma_squared > 0.0 {
+            let i = log_radii.partition_point(|log_radius| *log_radius < bin.radius.ln());
+            let slope = local_slope(&log_radii, &log_pressure, i.min(log_radii.len() - 1));
+            let anisotropy = bin.sigma_tangential * bin.sigma_tangential / sigma_squared;
+            mean_speed_squared += sigma_squared * (1.0 - anisotropy + slope);
+        }
+        // Asymmetric drift only ever slows the rotation down in a real disc.
+        bin.mean_speed = mean_speed_squared
+            .clamp(0.0, bin.circular_speed_squared)
+            .sqrt();
+    }
+}
+
+// Slope of a least squares line through the points within `SLOPE_WINDOW` of point `i`, so the
+// derivatives are not taken from the noise between neighbouring bins.
+fn local_slope(xs: &[f64], ys: &[f64], i: usize) -> f64 {
+    let start = i.saturating_sub(SLOPE_WINDOW);
+    let end = (i + SLOPE_WINDOW + 1).min(xs.len());
+    let count = (end - start) as f64;
+    let mean_x = xs[start..end].iter().sum::<f64>() / count;
+    let mean_y = ys[start..end].iter().sum::<f64>() / count;
+    let mut covariance = 0.0;
+    let mut variance = 0.0;
+    for j in start..end {
+        covariance += (xs[j] - mean_x) * (ys[j] - mean_y);
+        variance += (xs[j] - mean_x) * (xs[j] - mean_x);
+    }
+    if variance > 0.0 {
+        covariance / variance
+    } else {
+        0.0
+    }
+}
+
+fn interpolate_bins(bins: &[RadialBin], radius: f64) -> (f64, f64, f64) {
+    let values = |bin: &RadialBin| (bin.mean_speed, bin.sigma_radial, bin.sigma_tangential);
+    let next = bins.partition_point(|bin| bin.radius < radius);
+    if next == 0 {
+        return values(&bins[0]);
+    }
+    if next == bins.len() {
+        return values(&bins[bins.len() - 1]);
+    }
+    let inner = values(&bins[next - 1]);
+    let outer = values(&bins[next]);
+    let t = (radius - bins[next - 1].radius) / (bins[next].radius - bins[next - 1].radius);
+    (
+        inner.0 + (outer.0 - inner.0) * t,
+        inner.1 + (outer.1 - inner.1) * t,
+        inner.2 + (outer.2 - inner.2) * t,
+    )
+}
+
+fn gaussian<R: Rng>(rng: &mut R) -> f64 {
+    let u1 = 1.0 - rng.gen::<f64>();
+    let u2 = rng.gen::<f64>();
+    (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos()
+}
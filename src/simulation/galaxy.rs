@@ -2,15 +2,15 @@ use super::*;
 use rand::Rng;
 use std::{f64::consts::PI, net::ToSocketAddrs};
 
-fn milky_way() -> World {
-    todo!();
+pub fn distrobution_mass(density_fn: fn(radius: f64, z: f64) -> f64, r_max: f64, z_max: f64, steps_r: u32, steps_z: u32) -> f64 {
+    distrobution_cells(density_fn, r_max, z_max, steps_r, steps_z).iter().sum()
 }
 
-pub fn distrobution_mass(density_fn: fn(radius: f64, z: f64) -> f64, r_max: f64, z_max: f64, steps_r: u32, steps_z: u32) -> f64 {
+// Mass of every (r, z) cell of the grid, with z running fastest.
+fn distrobution_cells(density_fn: fn(radius: f64, z: f64) -> f64, r_max: f64, z_max: f64, steps_r: u32, steps_z: u32) -> Vec<f64> {
     let r_delta = r_max / steps_r as f64;
     let z_delta = z_max / steps_z as f64 * 2.0;
-    let mut rng = rand::thread_rng();
-    let mut total_mass = 0.0;
+    let mut cells = vec![];
     for r_index in 0..steps_r {
         let r = r_index as f64 * r_delta;
         for z_index in 0..steps_z {
@@ -18,10 +18,26 @@ pub fn distrobution_mass(density_fn: fn(radius: f64, z: f64) -> f64, r_max: f64,
 
             let density = density_fn(r, z);
             let volume = PI * ((r + r_delta).powi(2) - r * r) * z_delta;
-            total_mass += density * volume;
+            cells.push(density * volume);
         }
     }
-    total_mass
+    cells
+}
+
+// Mean surface density of the annulus from `inner` to `outer`, counting only |z| < z_max like the
+// sampling does. For comparing measured profiles with the input.
+pub fn distrobution_surface_density(density_fn: fn(radius: f64, z: f64) -> f64, inner: f64, outer: f64, z_max: f64, steps_r: u32, steps_z: u32) -> f64 {
+    let r_delta = (outer - inner) / steps_r as f64;
+    let z_delta = z_max / steps_z as f64 * 2.0;
+    let mut mass = 0.0;
+    for r_index in 0..steps_r {
+        let r = inner + (r_index as f64 + 0.5) * r_delta;
+        for z_index in 0..steps_z {
+            let z = (z_index as f64 + 0.5) * z_delta - z_max;
+            mass += density_fn(r, z) * 2.0 * PI * r * r_delta * z_delta;
+        }
+    }
+    mass / (PI * (outer * outer - inner * inner))
 }
 
 pub fn from_distrobution(
@@ -32,7 +48,31 @@ pub fn from_distrobution(
     steps_r: u32,
     steps_z: u32,
 ) -> World {
-    let total_mass = distrobution_mass(density_fn, r_max, z_max, steps_r, steps_z);
+    from_distrobution_weighted(density_fn, num_particles, r_max, z_max, steps_r, steps_z, 1.0)
+}
+
+// Draws exactly `num_particles` cells with probability proportional to mass^weighting and gives
+// each particle the mass that keeps the total unbiased. A weighting of 1 gives equal masses, lower
+// weightings spend more (lighter) particles on the low density outskirts.
+pub fn from_distrobution_weighted(
+    density_fn: fn(radius: f64, z: f64) -> f64,
+    num_particles: u32,
+    r_max: f64,
+    z_max: f64,
+    steps_r: u32,
+    steps_z: u32,
+    weighting: f64,
+) -> World {
+    let cells = distrobution_cells(density_fn, r_max, z_max, steps_r, steps_z);
+
+    let mut cumulative = vec![];
+    let mut total_weight = 0.0;
+    for mass in &cells {
+        if *mass > 0.0 {
+            total_weight += mass.powf(weighting);
+        }
+        cumulative.push(total_weight);
+    }
 
     let mut world = World::new(WorldSettings {
         gravity_strength: 0.0,
@@ -42,121 +82,268 @@ pub fn from_distrobution(
         multiprocessing: false,
     });
 
-    let mass_per_particle = total_mass / num_particles as f64;
-    println!("{} {}",total_mass, mass_per_particle);
-
     let r_delta = r_max / steps_r as f64;
     let z_delta = z_max / steps_z as f64 * 2.0;
     let mut rng = rand::thread_rng();
-    for r_index in 0..steps_r {
-        let r = r_index as f64 * r_delta;
-        let mut mass = 0.0;
-        for z_index in 0..steps_z {
-            let z = z_index as f64 * z_delta - z_max;
+    for _ in 0..num_particles {
+        let target = rng.gen::<f64>() * total_weight;
+        let cell = cumulative.partition_point(|weight| *weight <= target).min(cells.len() - 1);
+        let r_index = cell / steps_z as usize;
+        let z_index = cell % steps_z as usize;
 
-            let density = density_fn(r, z);
-            let volume = PI * ((r + r_delta).powi(2) - r * r) * z_delta;
-            mass += density * volume;
-        }
+        let probability = cells[cell].powf(weighting) / total_weight;
+        let mass = cells[cell] / (num_particles as f64 * probability);
 
-        let n = ((mass / total_mass) * num_particles as f64) as u32;
-        for _ in 0..n {
-            let angle = rng.gen::<f64>() * 2.0 * std::f64::consts::PI;
-            let distance = r + r_delta * rng.gen::<f64>();
-            let position = Vector2 {
-                x: angle.cos() * distance,
-                y: angle.sin() * distance,
-            };
-            world.add_particle(Particle {
-                mass: mass_per_particle,
-                position,
-                velocity: Vector2 { x: 0.0, y: 0.0 },
-                color: (1.0, 1.0, 1.0),
-            });
-        }
+        let r = r_index as f64 * r_delta;
+        let angle = rng.gen::<f64>() * 2.0 * std::f64::consts::PI;
+        let distance = (r * r + rng.gen::<f64>() * ((r + r_delta).powi(2) - r * r)).sqrt();
+        let position = Vector2 {
+            x: angle.cos() * distance,
+            y: angle.sin() * distance,
+        };
+        let z = (z_index as f64 + rng.gen::<f64>()) * z_delta - z_max;
+        world.add_particle(Particle {
+            mass,
+            position,
+            z,
+            velocity: Vector2 { x: 0.0, y: 0.0 },
+            color: (1.0, 1.0, 1.0),
+            tag: 0,
+            potential: 0.0,
+        });
     }
 
     world
 }
 
-pub fn milkyway() -> World {
-    let bulge_density = |r: f64, z: f64| {
-        let a = 1.8;
-        let r0 = 0.075;
-        let rcut = 2.1;
-        let q = 0.5;
-        let p0b = 98.4e9;
-
-        let rp = (r * r + (z / q).powi(2)).sqrt();
-        p0b / (1.0 + rp / r0).powf(a) * (-(rp / rcut).powi(2)).exp()
-    };
+#[derive(Clone, Copy, Debug)]
+pub enum ParticleBudget {
+    Count(u32),
+    // Share of the particles left over after the fixed counts, proportional to the component mass.
+    MassFraction,
+}
+
+#[derive(Clone)]
+pub struct GalaxyComponent {
+    pub name: String,
+    pub density_fn: fn(radius: f64, z: f64) -> f64,
+    pub particles: ParticleBudget,
+    pub color: (f64, f64, f64),
+    pub tag: u32,
+    pub dispersion: Dispersion,
+}
 
-    let thin_disc_density = |r: f64, z: f64| {
-        let sigma0 = 886.7e6;
-        let zd = 2.6;
-        let rd = 2.53;
+#[derive(Clone, Debug)]
+pub struct ComponentReport {
+    pub name: String,
+    pub tag: u32,
+    pub mass: f64,
+    pub particles: u32,
+}
 
-        sigma0/(2.0*zd)*(-z.abs()/zd-r/rd).exp()
-    };
+pub struct GalaxyBuilder {
+    settings: WorldSettings,
+    total_particles: u32,
+    components: Vec<GalaxyComponent>,
+    extra_particles: Vec<Particle>,
+    r_max: f64,
+    z_max: f64,
+    steps_r: u32,
+    steps_z: u32,
+    circular_speed: CircularSpeed,
+    num_bins: usize,
+}
 
-    let thick_disc_density = |r: f64, z: f64| {
-        let sigma0 = 156.7e6;
-        let zd = 3.6;
-        let rd = 3.38;
+impl GalaxyBuilder {
+    pub fn new(settings: WorldSettings, total_particles: u32) -> Self {
+        Self {
+            settings,
+            total_particles,
+            components: vec![],
+            extra_particles: vec![],
+            r_max: 25.0,
+            z_max: 0.5,
+            steps_r: 1000,
+            steps_z: 100,
+            circular_speed: CircularSpeed::Tree,
+            num_bins: 100,
+        }
+    }
 
-        sigma0/(2.0*zd)*(-z.abs()/zd-r/rd).exp()
-    };
+    pub fn grid(mut self, r_max: f64, z_max: f64, steps_r: u32, steps_z: u32) -> Self {
+        self.r_max = r_max;
+        self.z_max = z_max;
+        self.steps_r = steps_r;
+        self.steps_z = steps_z;
+        self
+    }
 
-    let gas_disc1_density = |r: f64, z: f64| {
-        let sigma0 = 53.1e6;
-        let zd = 0.085;
-        let rm = 4.0;
-        let rd = 7.0;
+    pub fn equilibrium(mut self, circular_speed: CircularSpeed, num_bins: usize) -> Self {
+        self.circular_speed = circular_speed;
+        self.num_bins = num_bins;
+        self
+    }
 
-        let x = z / (2.0*zd);
-        sigma0/(4.0*zd)*(-rm/r-r/rd).exp()*(2.0/(x.exp()+(-x).exp())).powi(2)
-    };
+    pub fn component(mut self, component: GalaxyComponent) -> Self {
+        self.components.push(component);
+        self
+    }
 
-    let gas_disc2_density = |r: f64, z: f64| {
-        let sigma0 = 2180.0e6;
-        let zd = 0.045;
-        let rm = 12.0;
-        let rd = 1.5;
+    // Single particles such as a central black hole, added as they are.
+    pub fn particle(mut self, particle: Particle) -> Self {
+        self.extra_particles.push(particle);
+        self
+    }
 
-        let x = z / (2.0*zd);
-        sigma0/(4.0*zd)*(-rm/r-r/rd).exp()*(2.0/(x.exp()+(-x).exp())).powi(2)
-    };
+    pub fn build(&self) -> (World, Vec<ComponentReport>) {
+        for (i, component) in self.components.iter().enumerate() {
+            for other in &self.components[..i] {
+                if component.name == other.name {
+                    panic!("galaxy component {} is included more than once", component.name);
+                }
+                if component.tag == other.tag {
+                    panic!("galaxy components {} and {} share tag {}", other.name, component.name, component.tag);
+                }
+            }
+            if self.extra_particles.iter().any(|particle| particle.tag == component.tag) {
+                panic!("galaxy component {} shares tag {} with a single particle", component.name, component.tag);
+            }
+        }
+
+        let masses: Vec<f64> = self
+            .components
+            .iter()
+            .map(|component| distrobution_mass(component.density_fn, self.r_max, self.z_max, self.steps_r, self.steps_z))
+            .collect();
+        let counts = self.particle_counts(&masses);
+
+        let mut world = World::new(self.settings.clone());
+        let mut reports = vec![];
+        for ((component, mass), count) in self.components.iter().zip(&masses).zip(counts) {
+            let mut component_world = from_distrobution(component.density_fn, count, self.r_max, self.z_max, self.steps_r, self.steps_z);
+            component_world.set_color(component.color);
+            component_world.set_tag(component.tag);
+            world.add_world(&component_world);
+
+            reports.push(ComponentReport {
+                name: component.name.clone(),
+                tag: component.tag,
+                mass: *mass,
+                particles: count,
+            });
+        }
+        for particle in &self.extra_particles {
+            world.add_particle(particle.clone());
+        }
+
+        let mut equilibrium = EquilibriumSettings {
+            circular_speed: self.circular_speed,
+            dispersion: Dispersion::Cold,
+            num_bins: self.num_bins,
+        };
+        for component in &self.components {
+            equilibrium.dispersion = component.dispersion;
+            world.set_equilibrium_velocity(&equilibrium, |particle| particle.tag == component.tag);
+        }
+
+        (world, reports)
+    }
 
-    let r_max = 25.0;
-    let z_max = 0.5;
-    let steps_r = 1000;
-    let steps_z = 100;
+    // Fixed counts are used as given and the rest is split by mass, rounding by largest remainder
+    // so the total comes out exact.
+    fn particle_counts(&self, masses: &[f64]) -> Vec<u32> {
+        let mut fixed = 0;
+        let mut shared_mass = 0.0;
+        for (component, mass) in self.components.iter().zip(masses) {
+            match component.particles {
+                ParticleBudget::Count(count) => fixed += count,
+                ParticleBudget::MassFraction => shared_mass += mass,
+            }
+        }
+        if fixed > self.total_particles {
+            panic!("galaxy components ask for {} particles but the budget is {}", fixed, self.total_particles);
+        }
+        let remaining = self.total_particles - fixed;
+
+        let mut counts = vec![];
+        let mut remainders = vec![];
+        for (i, (component, mass)) in self.components.iter().zip(masses).enumerate() {
+            match component.particles {
+                ParticleBudget::Count(count) => counts.push(count),
+                ParticleBudget::MassFraction => {
+                    let share = mass / shared_mass * remaining as f64;
+                    counts.push(share as u32);
+                    remainders.push((share - share.floor(), i));
+                }
+            }
+        }
 
-    let bulge_mass = distrobution_mass(bulge_density, r_max, z_max, steps_r, steps_z);
-    let thin_disc_mass = distrobution_mass(thin_disc_density, r_max, z_max, steps_r, steps_z);
-    let thick_disc_mass = distrobution_mass(thick_disc_density, r_max, z_max, steps_r, steps_z);
-    let gas_disc1_mass = distrobution_mass(gas_disc1_density, r_max, z_max, steps_r, steps_z);
-    let gas_disc2_mass = distrobution_mass(gas_disc2_density, r_max, z_max, steps_r, steps_z);
-    
-    let total_mass = bulge_mass + thin_disc_mass + thick_disc_mass + gas_disc1_mass + gas_disc2_mass;
+        let assigned: u32 = counts.iter().sum();
+        remainders.sort_by(|a, b| b.0.total_cmp(&a.0));
+        for (_, i) in remainders.iter().take((self.total_particles - assigned) as usize) {
+            counts[*i] += 1;
+        }
+        counts
+    }
+}
 
-    let total_particles = 100000;
-    let bulge_particles = (bulge_mass / total_mass * total_particles as f64) as u32;
-    let thin_disc_particles = (thin_disc_mass / total_mass * total_particles as f64) as u32;
-    let thick_disc_particles = (thick_disc_mass / total_mass * total_particles as f64) as u32;
-    let gas_disc1_particles = (gas_disc1_mass / total_mass * total_particles as f64) as u32;
-    let gas_disc2_particles = (gas_disc2_mass / total_mass * total_particles as f64) as u32;
+pub fn milkyway_bulge_density(r: f64, z: f64) -> f64 {
+    let a = 1.8;
+    let r0 = 0.075;
+    let rcut = 2.1;
+    let q = 0.5;
+    let p0b = 98.4e9;
 
-    let mut bulge_world = from_distrobution(bulge_density, bulge_particles, r_max, z_max, steps_r, steps_z);
+    let rp = (r * r + (z / q).powi(2)).sqrt();
+    p0b / (1.0 + rp / r0).powf(a) * (-(rp / rcut).powi(2)).exp()
+}
 
-    let mut thin_disc_world = from_distrobution(thin_disc_density, thin_disc_particles, r_max, z_max, steps_r, steps_z);
+pub fn milkyway_thin_disc_density(r: f64, z: f64) -> f64 {
+    let sigma0 = 886.7e6;
+    let zd = 2.6;
+    let rd = 2.53;
 
-    let mut thick_disc_world = from_distrobution(thick_disc_density, thick_disc_particles, r_max, z_max, steps_r, steps_z);
+    sigma0/(2.0*zd)*(-z.abs()/zd-r/rd).exp()
+}
 
-    let mut gas_disc1_world = from_distrobution(gas_disc1_density, gas_disc1_particles, r_max, z_max, steps_r, steps_z);
+pub fn milkyway_thick_disc_density(r: f64, z: f64) -> f64 {
+    let sigma0 = 156.7e6;
+    let zd = 3.6;
+    let rd = 3.38;
 
-    let mut gas_disc2_world = from_distrobution(gas_disc2_density, gas_disc2_particles, r_max, z_max, steps_r, steps_z);
+    sigma0/(2.0*zd)*(-z.abs()/zd-r/rd).exp()
+}
 
+pub fn milkyway_gas_disc1_density(r: f64, z: f64) -> f64 {
+    let sigma0 = 53.1e6;
+    let zd = 0.085;
+    let rm = 4.0;
+    let rd = 7.0;
+
+    let x = z / (2.0*zd);
+    sigma0/(4.0*zd)*(-rm/r-r/rd).exp()*(2.0/(x.exp()+(-x).exp())).powi(2)
+}
+
+pub fn milkyway_gas_disc2_density(r: f64, z: f64) -> f64 {
+    let sigma0 = 2180.0e6;
+    let zd = 0.045;
+    let rm = 12.0;
+    let rd = 1.5;
+
+    let x = z / (2.0*zd);
+    sigma0/(4.0*zd)*(-rm/r-r/rd).exp()*(2.0/(x.exp()+(-x).exp())).powi(2)
+}
+
+// All of the Milky Way except the central black hole.
+pub fn milkyway_density(r: f64, z: f64) -> f64 {
+    milkyway_bulge_density(r, z)
+        + milkyway_thin_disc_density(r, z)
+        + milkyway_thick_disc_density(r, z)
+        + milkyway_gas_disc1_density(r, z)
+        + milkyway_gas_disc2_density(r, z)
+}
+
+pub fn milkyway() -> World {
     let settings = WorldSettings {
         gravity_strength: 1.30128e-12, //1e20 aar
         softening_length: 0.1,
@@ -165,28 +352,38 @@ pub fn milkyway() -> World {
         multiprocessing: true,
     };
 
-    bulge_world.set_color((1.0, 0.0, 0.0));
-    thin_disc_world.set_color((0.0, 1.0, 0.0));
-    thick_disc_world.set_color((0.0, 1.0, 0.0));
-    gas_disc1_world.set_color((0.0, 0.0, 1.0));
-    gas_disc2_world.set_color((0.0, 0.0, 1.0));
-
     let sagittarius = Particle {
         mass: 4.297e6,
         position: Vector2{x: 0.00001, y: 0.00001},
+        z: 0.0,
         velocity: Vector2{x: 0.0, y: 0.0},
         color: (1.0, 1.0, 1.0),
+        tag: 6,
+        potential: 0.0,
     };
 
-    let mut milky_way = World::new(settings.clone());
-    milky_way.add_world(&bulge_world);
-    milky_way.add_world(&thick_disc_world);
-    milky_way.add_world(&thick_disc_world);
-    milky_way.add_world(&gas_disc1_world);
-    milky_way.add_world(&gas_disc2_world);
-    milky_way.add_particle(sagittarius);
+    let component = |name: &str, density_fn: fn(f64, f64) -> f64, color, tag, dispersion| GalaxyComponent {
+        name: name.to_string(),
+        density_fn,
+        particles: ParticleBudget::MassFraction,
+        color,
+        tag,
+        dispersion,
+    };
 
-    milky_way.set_circle_speed(true);
+    let (milky_way, reports) = GalaxyBuilder::new(settings, 100000)
+        .grid(25.0, 0.5, 1000, 100)
+        .component(component("bulge", milkyway_bulge_density, (1.0, 0.0, 0.0), 1, Dispersion::Isotropic))
+        .component(component("thin disc", milkyway_thin_disc_density, (0.0, 1.0, 0.0), 2, Dispersion::ToomreQ(1.5)))
+        .component(component("thick disc", milkyway_thick_disc_density, (0.0, 1.0, 0.0), 3, Dispersion::ToomreQ(2.0)))
+        .component(component("gas disc 1", milkyway_gas_disc1_density, (0.0, 0.0, 1.0), 4, Dispersion::ToomreQ(1.5)))
+        .component(component("gas disc 2", milkyway_gas_disc2_density, (0.0, 0.0, 1.0), 5, Dispersion::ToomreQ(1.5)))
+        .particle(sagittarius)
+        .build();
+
+    for report in &reports {
+        println!("{}: {:e} solar masses in {} particles", report.name, report.mass, report.particles);
+    }
 
     milky_way
 }
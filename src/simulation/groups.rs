@@ -0,0 +1,142 @@
+use super::*;
+use std::fmt::Write;
+use std::fs;
+
+#[derive(Clone, Copy, Debug)]
+pub struct FofSettings {
+    // Particles closer than this are friends, and friends of friends end up in the same group.
+    pub linking_length: f64,
+    // Smaller groups are dropped and their particles left ungrouped.
+    pub min_members: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct Group {
+    // Indices into `World::particles`.
+    pub members: Vec<usize>,
+    pub mass: f64,
+    pub center: Vector2,
+    pub velocity: Vector2,
+}
+
+#[derive(Clone, Debug)]
+pub struct GroupCatalog {
+    // Heaviest first.
+    pub groups: Vec<Group>,
+    // Group of every particle in the world, or None for particles in no group.
+    pub membership: Vec<Option<usize>>,
+}
+
+impl World {
+    // Friends-of-friends groups, e.g. to find the clumps, tails and satellites left by a merger.
+    pub fn find_groups(&self, settings: &FofSettings) -> GroupCatalog {
+        let tree = KdTree::from_world(self);
+        let mut sets = DisjointSets::new(self.particles.len());
+        for (i, particle) in self.particles.iter().enumerate() {
+            for j in tree.within(particle.position, settings.linking_length) {
+                if j > i {
+                    sets.union(i, j);
+                }
+            }
+        }
+
+        let mut members_of_root = vec![vec![]; self.particles.len()];
+        for i in 0..self.particles.len() {
+            members_of_root[sets.find(i)].push(i);
+        }
+
+        let mut groups: Vec<Group> = members_of_root
+            .into_iter()
+            .filter(|members| !members.is_empty() && members.len() >= settings.min_members)
+            .map(|members| self.group(members))
+            .collect();
+        groups.sort_by(|a, b| b.mass.total_cmp(&a.mass));
+
+        let mut membership = vec![None; self.particles.len()];
+        for (g, group) in groups.iter().enumerate() {
+            for &i in &group.members {
+                membership[i] = Some(g);
+            }
+        }
+        GroupCatalog { groups, membership }
+    }
+
+    pub(super) fn group(&self, members: Vec<usize>) -> Group {
+        let mut mass = 0.0;
+        let mut center = Vector2 { x: 0.0, y: 0.0 };
+        let mut velocity = Vector2 { x: 0.0, y: 0.0 };
+        for &i in &members {
+            let particle = &self.particles[i];
+            mass += particle.mass;
+            center += particle.position * particle.mass;
+            velocity += particle.velocity * particle.mass;
+        }
+        if mass > 0.0 {
+            center /= mass;
+            velocity /= mass;
+        }
+        Group {
+            members,
+            mass,
+            center,
+            velocity,
+        }
+    }
+}
+
+impl GroupCatalog {
+    // One row per group with the columns `group,members,mass,x,y,vx,vy`.
+    pub fn save_csv(&self, path: &str) {
+        let mut csv = String::from("group,members,mass,x,y,vx,vy\n");
+        for (g, group) in self.groups.iter().enumerate() {
+            writeln!(
+                csv,
+                "{},{},{},{},{},{},{}",
+                g,
+                group.members.len(),
+                group.mass,
+                group.center.x,
+                group.center.y,
+                group.velocity.x,
+                group.velocity.y
+            )
+            .unwrap();
+        }
+        fs::write(path, csv).unwrap();
+    }
+}
+
+// Union-find with path halving and union by size.
+struct DisjointSets {
+    parent: Vec<usize>,
+    size: Vec<usize>,
+}
+
+impl DisjointSets {
+    fn new(len: usize) -> Self {
+        Self {
+            parent: (0..len).collect(),
+            size: vec![1; len],
+        }
+    }
+
+    fn find(&mut self, mut i: usize) -> usize {
+        while self.parent[i] != i {
+            self.parent[i] = self.parent[self.parent[i]];
+            i = self.parent[i];
+        }
+        i
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let (mut a, mut b) = (self.find(a), self.find(b));
+        if a == b {
+            return;
+        }
+        if self.size[a] < self.size[b] {
+            (a, b) = (b, a);
+        }
+        self.parent[b] = a;
+        self.size[a] += self.size[b];
+    }
+}
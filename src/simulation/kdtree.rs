@@ -0,0 +1,161 @@
+use super::*;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+// Static 2D k-d tree stored implicitly: every slice of `order` has its splitting point at the
+// middle, with smaller coordinates to the left.
+pub struct KdTree {
+    points: Vec<Vector2>,
+    order: Vec<usize>,
+}
+
+struct Neighbour {
+    distance_squared: f64,
+    index: usize,
+}
+
+impl PartialEq for Neighbour {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Neighbour {}
+
+impl PartialOrd for Neighbour {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Neighbour {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.distance_squared.total_cmp(&other.distance_squared)
+    }
+}
+
+impl KdTree {
+    pub fn new(points: Vec<Vector2>) -> Self {
+        let mut order: Vec<usize> = (0..points.len()).collect();
+        Self::build(&points, &mut order, 0);
+        Self { points, order }
+    }
+
+    pub fn from_world(world: &World) -> Self {
+        Self::new(world.particles.iter().map(|particle| particle.position).collect())
+    }
+
+    fn build(points: &[Vector2], order: &mut [usize], depth: usize) {
+        if order.len() <= 1 {
+            return;
+        }
+        let middle = order.len() / 2;
+        order.select_nth_unstable_by(middle, |a, b| {
+            coordinate(points[*a], depth).total_cmp(&coordinate(points[*b], depth))
+        });
+        let (left, right) = order.split_at_mut(middle);
+        Self::build(points, left, depth + 1);
+        Self::build(points, &mut right[1..], depth + 1);
+    }
+
+    // The k nearest points as (index, distance), closest first. A point at `position` itself is
+    // included.
+    pub fn nearest(&self, position: Vector2, k: usize) -> Vec<(usize, f64)> {
+        let mut heap = BinaryHeap::new();
+        if k > 0 {
+            self.search_nearest(0, self.order.len(), 0, position, k, &mut heap);
+        }
+        heap.into_sorted_vec()
+            .iter()
+            .map(|neighbour| (neighbour.index, neighbour.distance_squared.sqrt()))
+            .collect()
+    }
+
+    fn search_nearest(
+        &self,
+        start: usize,
+        end: usize,
+        depth: usize,
+        position: Vector2,
+        k: usize,
+        heap: &mut BinaryHeap<Neighbour>,
+    ) {
+        if start >= end {
+            return;
+        }
+        let middle = (start + end) / 2;
+        let index = self.order[middle];
+        let point = self.points[index];
+
+        let difference = point - position;
+        let distance_squared = difference.dot(difference);
+        if heap.len() < k {
+            heap.push(Neighbour {
+                distance_squared,
+                index,
+            });
+        } else if distance_squared < heap.peek().unwrap().distance_squared {
+            heap.pop();
+            heap.push(Neighbour {
+                distance_squared,
+                index,
+            });
+        }
+
+        let offset = coordinate(position, depth) - coordinate(point, depth);
+        let (near, far) = if offset < 0.0 {
+            ((start, middle), (middle + 1, end))
+        } else {
+            ((middle + 1, end), (start, middle))
+        };
+        self.search_nearest(near.0, near.1, depth + 1, position, k, heap);
+        if heap.len() < k || offset * offset < heap.peek().unwrap().distance_squared {
+            self.search_nearest(far.0, far.1, depth + 1, position, k, heap);
+        }
+    }
+
+    // Indices of all points within `radius` of `position`, in no particular order. A point at
+    // `position` itself is included.
+    pub fn within(&self, position: Vector2, radius: f64) -> Vec<usize> {
+        let mut found = vec![];
+        self.search_within(0, self.order.len(), 0, position, radius, &mut found);
+        found
+    }
+
+    fn search_within(
+        &self,
+        start: usize,
+        end: usize,
+        depth: usize,
+        position: Vector2,
+        radius: f64,
+        found: &mut Vec<usize>,
+    ) {
+        if start >= end {
+            return;
+        }
+        let middle = (start + end) / 2;
+        let index = self.order[middle];
+        let point = self.points[index];
+
+        let difference = point - position;
+        if difference.dot(difference) <= radius * radius {
+            found.push(index);
+        }
+
+        let offset = coordinate(position, depth) - coordinate(point, depth);
+        if offset <= radius {
+            self.search_within(start, middle, depth + 1, position, radius, found);
+        }
+        if offset >= -radius {
+            self.search_within(middle + 1, end, depth + 1, position, radius, found);
+        }
+    }
+}
+
+fn coordinate(vector: Vector2, depth: usize) -> f64 {
+    match depth % 2 {
+        0 => vector.x,
+        _ => vector.y,
+    }
+}
@@ -0,0 +1,304 @@
+use super::*;
+
+// Annotations drawn into the 8 bit image after tone mapping. The float outputs are left untouched.
+#[derive(Clone, Debug)]
+pub enum Overlay {
+    // Bar of a round length at the bottom left. World lengths are multiplied by `unit_scale` and
+    // labelled with `unit`.
+    ScaleBar { unit: String, unit_scale: f64 },
+    // `World::time` at the top left, converted like the scale bar.
+    Time { unit: String, unit_scale: f64 },
+    // Frame number at the top right.
+    FrameCounter,
+    // Color swatches with labels at the bottom right, e.g. one per tag.
+    Legend(Vec<(String, (f64, f64, f64))>),
+    // The colormap of the color mode with its range at the bottom right.
+    Colorbar { label: String },
+}
+
+// What the overlays show about a frame.
+pub(super) struct FrameInfo<'a> {
+    pub pixels_per_unit: f64,
+    pub time: f64,
+    pub frame: u64,
+    pub color_mode: &'a ColorMode,
+    pub range: (f64, f64),
+}
+
+const WHITE: [u8; 3] = [255, 255, 255];
+
+pub(super) fn draw_overlays(image: &mut image::RgbImage, overlays: &[Overlay], info: &FrameInfo) {
+    let (width, height) = (image.width() as i64, image.height() as i64);
+    // Glyphs are scaled up by whole pixels so the text stays readable on large frames.
+    let scale = (height / 360).max(1);
+    let margin = 8 * scale;
+    let line_height = 10 * scale;
+    // Items at the bottom right are stacked upwards from here.
+    let mut bottom_right = height - margin;
+
+    for overlay in overlays {
+        match overlay {
+            Overlay::ScaleBar { unit, unit_scale } => {
+                let length = round_length(width as f64 / 5.0 / info.pixels_per_unit);
+                let bar_width = (length * info.pixels_per_unit).round() as i64;
+                let y = height - margin - 2 * scale;
+                fill_rect(image, margin, y, bar_width, 2 * scale, WHITE);
+                let label = format!("{} {}", format_number(length * unit_scale), unit);
+                draw_text(image, margin, y - line_height, &label, scale, WHITE);
+            }
+            Overlay::Time { unit, unit_scale } => {
+                let label = format!("T = {} {}", format_number(info.time * unit_scale), unit);
+                draw_text(image, margin, margin, &label, scale, WHITE);
+            }
+            Overlay::FrameCounter => {
+                let label = format!("FRAME {}", info.frame);
+                let x = width - margin - text_width(&label, scale);
+                draw_text(image, x, margin, &label, scale, WHITE);
+            }
+            Overlay::Legend(entries) => {
+                let entries_width = entries
+                    .iter()
+                    .map(|(label, _)| text_width(label, scale))
+                    .max()
+                    .unwrap_or(0);
+                let x = width - margin - entries_width - 10 * scale;
+                for (label, color) in entries.iter().rev() {
+                    bottom_right -= line_height;
+                    fill_rect(image, x, bottom_right, 7 * scale, 7 * scale, to_rgb(*color));
+                    draw_text(image, x + 10 * scale, bottom_right, label, scale, WHITE);
+                }
+                bottom_right -= line_height / 2;
+            }
+            Overlay::Colorbar { label } => {
+                let (colormap, range) = match info.color_mode {
+                    ColorMode::Density(colormap) => (colormap, None),
+                    ColorMode::Quantity { colormap, .. }
+                    | ColorMode::MassWeighted { colormap, .. } => (colormap, Some(info.range)),
+                    ColorMode::ParticleColor => continue,
+                };
+                let bar_width = 100 * scale;
+                let x = width - margin - bar_width;
+
+                bottom_right -= 6 * scale;
+                for i in 0..bar_width {
+                    let color = colormap.color(i as f64 / (bar_width - 1) as f64);
+                    fill_rect(image, x + i, bottom_right, 1, 6 * scale, to_rgb(color));
+                }
+                if let Some((min, max)) = range {
+                    bottom_right -= line_height;
+                    draw_text(image, x, bottom_right, &format_number(min), scale, WHITE);
+                    let max = format_number(max);
+                    let max_x = width - margin - text_width(&max, scale);
+                    draw_text(image, max_x, bottom_right, &max, scale, WHITE);
+                }
+                bottom_right -= line_height;
+                draw_text(image, x, bottom_right, label, scale, WHITE);
+                bottom_right -= line_height / 2;
+            }
+        }
+    }
+}
+
+// The largest 1, 2 or 5 times a power of ten not above `length`.
+fn round_length(length: f64) -> f64 {
+    let power = 10f64.powf(length.log10().floor());
+    let mantissa = length / power;
+    let step = if mantissa >= 5.0 {
+        5.0
+    } else if mantissa >= 2.0 {
+        2.0
+    } else {
+        1.0
+    };
+    step * power
+}
+
+fn format_number(value: f64) -> String {
+    if value == 0.0 {
+        return "0".to_string();
+    }
+    if value.abs() >= 1e4 || value.abs() < 1e-2 {
+        return format!("{:.1e}", value);
+    }
+    let text = format!("{:.3}", value);
+    text.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+pub(super) fn to_rgb(color: (f64, f64, f64)) -> [u8; 3] {
+    let to_u8 = |value: f64| (value.clamp(0.0, 1.0) * 255.0) as u8;
+    [to_u8(color.0), to_u8(color.1), to_u8(color.2)]
+}
+
+pub(super) fn fill_rect(
+    image: &mut image::RgbImage,
+    x: i64,
+    y: i64,
+    width: i64,
+    height: i64,
+    color: [u8; 3],
+) {
+    for pixel_y in y.max(0)..(y + height).min(image.height() as i64) {
+        for pixel_x in x.max(0)..(x + width).min(image.width() as i64) {
+            image.put_pixel(pixel_x as u32, pixel_y as u32, image::Rgb(color));
+        }
+    }
+}
+
+// Line of squares `width` pixels wide from `from` to `to`. The line is clipped to the image first,
+// so far off screen points only cost the steps of the visible part.
+pub(super) fn draw_line(
+    image: &mut image::RgbImage,
+    from: Vector2,
+    to: Vector2,
+    width: i64,
+    color: [u8; 3],
+) {
+    let margin = width as f64;
+    let min = Vector2 {
+        x: -margin,
+        y: -margin,
+    };
+    let max = Vector2 {
+        x: image.width() as f64 + margin,
+        y: image.height() as f64 + margin,
+    };
+    let Some((from, to)) = clip_line(from, to, min, max) else {
+        return;
+    };
+
+    let steps = (to.x - from.x)
+        .abs()
+        .max((to.y - from.y).abs())
+        .ceil()
+        .max(1.0) as i64;
+    for step in 0..=steps {
+        let point = from + (to - from) * (step as f64 / steps as f64);
+        let x = point.x.floor() as i64 - width / 2;
+        let y = point.y.floor() as i64 - width / 2;
+        fill_rect(image, x, y, width, width, color);
+    }
+}
+
+// Liang-Barsky clipping of the segment from `from` to `to` to the rectangle between `min` and
+// `max`. None if no part of it is inside or a coordinate is not finite.
+fn clip_line(from: Vector2, to: Vector2, min: Vector2, max: Vector2) -> Option<(Vector2, Vector2)> {
+    if ![from.x, from.y, to.x, to.y]
+        .iter()
+        .all(|value| value.is_finite())
+    {
+        return None;
+    }
+    let delta = to - from;
+    let (mut enter, mut exit) = (0.0f64, 1.0f64);
+    for (p, q) in [
+        (-delta.x, from.x - min.x),
+        (delta.x, max.x - from.x),
+        (-delta.y, from.y - min.y),
+        (delta.y, max.y - from.y),
+    ] {
+        if p == 0.0 {
+            if q < 0.0 {
+                return None;
+            }
+        } else if p < 0.0 {
+            enter = enter.max(q / p);
+        } else {
+            exit = exit.min(q / p);
+        }
+    }
+    if enter > exit {
+        return None;
+    }
+    // Rounding can leave the ends far outside for huge coordinates, so keep them in the rectangle.
+    let clamp = |point: Vector2| Vector2 {
+        x: point.x.clamp(min.x, max.x),
+        y: point.y.clamp(min.y, max.y),
+    };
+    Some((clamp(from + delta * enter), clamp(from + delta * exit)))
+}
+
+fn text_width(text: &str, scale: i64) -> i64 {
+    (text.chars().count() as i64 * 6 - 1).max(0) * scale
+}
+
+// Draws `text` with its top left corner at (x, y) and a dark shadow so it reads on bright regions.
+pub(super) fn draw_text(
+    image: &mut image::RgbImage,
+    x: i64,
+    y: i64,
+    text: &str,
+    scale: i64,
+    color: [u8; 3],
+) {
+    for (offset, color) in [(scale, [0, 0, 0]), (0, color)] {
+        for (i, character) in text.chars().enumerate() {
+            let glyph_x = x + offset + i as i64 * 6 * scale;
+            for (row, bits) in glyph(character).iter().enumerate() {
+                for column in 0..5 {
+                    if bits & (0b10000 >> column) != 0 {
+                        let pixel_x = glyph_x + column * scale;
+                        let pixel_y = y + offset + row as i64 * scale;
+                        fill_rect(image, pixel_x, pixel_y, scale, scale, color);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// 5x7 glyphs, one row per entry with the leftmost column in bit 4. Lowercase letters are drawn as
+// uppercase.
+#[rustfmt::skip]
+fn glyph(character: char) -> [u8; 7] {
+    match character.to_ascii_uppercase() {
+        ' ' => [0; 7],
+        '0' => [0b01110, 0b10001, 0b10011, 0b10101, 0b11001, 0b10001, 0b01110],
+        '1' => [0b00100, 0b01100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110],
+        '2' => [0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b01000, 0b11111],
+        '3' => [0b11111, 0b00010, 0b00100, 0b00010, 0b00001, 0b10001, 0b01110],
+        '4' => [0b00010, 0b00110, 0b01010, 0b10010, 0b11111, 0b00010, 0b00010],
+        '5' => [0b11111, 0b10000, 0b11110, 0b00001, 0b00001, 0b10001, 0b01110],
+        '6' => [0b00110, 0b01000, 0b10000, 0b11110, 0b10001, 0b10001, 0b01110],
+        '7' => [0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b01000, 0b01000],
+        '8' => [0b01110, 0b10001, 0b10001, 0b01110, 0b10001, 0b10001, 0b01110],
+        '9' => [0b01110, 0b10001, 0b10001, 0b01111, 0b00001, 0b00010, 0b01100],
+        'A' => [0b01110, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001],
+        'B' => [0b11110, 0b10001, 0b10001, 0b11110, 0b10001, 0b10001, 0b11110],
+        'C' => [0b01110, 0b10001, 0b10000, 0b10000, 0b10000, 0b10001, 0b01110],
+        'D' => [0b11100, 0b10010, 0b10001, 0b10001, 0b10001, 0b10010, 0b11100],
+        'E' => [0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b11111],
+        'F' => [0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b10000],
+        'G' => [0b01110, 0b10001, 0b10000, 0b10111, 0b10001, 0b10001, 0b01111],
+        'H' => [0b10001, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001],
+        'I' => [0b01110, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110],
+        'J' => [0b00111, 0b00010, 0b00010, 0b00010, 0b00010, 0b10010, 0b01100],
+        'K' => [0b10001, 0b10010, 0b10100, 0b11000, 0b10100, 0b10010, 0b10001],
+        'L' => [0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b11111],
+        'M' => [0b10001, 0b11011, 0b10101, 0b10101, 0b10001, 0b10001, 0b10001],
+        'N' => [0b10001, 0b10001, 0b11001, 0b10101, 0b10011, 0b10001, 0b10001],
+        'O' => [0b01110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110],
+        'P' => [0b11110, 0b10001, 0b10001, 0b11110, 0b10000, 0b10000, 0b10000],
+        'Q' => [0b01110, 0b10001, 0b10001, 0b10001, 0b10101, 0b10010, 0b01101],
+        'R' => [0b11110, 0b10001, 0b10001, 0b11110, 0b10100, 0b10010, 0b10001],
+        'S' => [0b01111, 0b10000, 0b10000, 0b01110, 0b00001, 0b00001, 0b11110],
+        'T' => [0b11111, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100],
+        'U' => [0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110],
+        'V' => [0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01010, 0b00100],
+        'W' => [0b10001, 0b10001, 0b10001, 0b10101, 0b10101, 0b10101, 0b01010],
+        'X' => [0b10001, 0b10001, 0b01010, 0b00100, 0b01010, 0b10001, 0b10001],
+        'Y' => [0b10001, 0b10001, 0b10001, 0b01010, 0b00100, 0b00100, 0b00100],
+        'Z' => [0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b11111],
+        '.' => [0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b01100, 0b01100],
+        ',' => [0b00000, 0b00000, 0b00000, 0b00000, 0b01100, 0b00100, 0b01000],
+        '-' => [0b00000, 0b00000, 0b00000, 0b11111, 0b00000, 0b00000, 0b00000],
+        '+' => [0b00000, 0b00100, 0b00100, 0b11111, 0b00100, 0b00100, 0b00000],
+        ':' => [0b00000, 0b01100, 0b01100, 0b00000, 0b01100, 0b01100, 0b00000],
+        '/' => [0b00000, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b00000],
+        '=' => [0b00000, 0b00000, 0b11111, 0b00000, 0b11111, 0b00000, 0b00000],
+        '%' => [0b11000, 0b11001, 0b00010, 0b00100, 0b01000, 0b10011, 0b00011],
+        '(' => [0b00010, 0b00100, 0b01000, 0b01000, 0b01000, 0b00100, 0b00010],
+        ')' => [0b01000, 0b00100, 0b00010, 0b00010, 0b00010, 0b00100, 0b01000],
+        '_' => [0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b11111],
+        _ => [0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b00000, 0b00100],
+    }
+}
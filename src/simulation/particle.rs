@@ -5,8 +5,14 @@ use serde::{Serialize, Deserialize};
 pub struct Particle {
     pub mass: f64,
     pub position: Vector2,
+    // Height above the plane; carried along for rendering but not integrated.
+    pub z: f64,
     pub velocity: Vector2,
     pub color: (f64, f64, f64),
+    pub tag: u32,
+    // Softened potential per unit mass, set by `World::update_potentials` and saved with the
+    // snapshots. Not kept up to date by `update`. Snapshots from before it was stored load with 0.
+    pub potential: f64,
 }
 
 impl Particle {
@@ -15,4 +21,39 @@ impl Particle {
         self.velocity += acceleration * delta_time;
         self.position += self.velocity * delta_time;
     }
+
+    pub fn position_3d(&self) -> Vector3 {
+        Vector3 {
+            x: self.position.x,
+            y: self.position.y,
+            z: self.z,
+        }
+    }
+}
+
+// Layout of `Particle` in snapshots written before they had a version header.
+#[derive(Deserialize)]
+struct ParticleV0 {
+    mass: f64,
+    position: Vector2,
+    velocity: Vector2,
+    color: (f64, f64, f64),
+}
+
+// Decodes a headerless snapshot. Bytes after the particles are ignored, like a tail left behind
+// when a shorter snapshot was written over a longer one. Fields the layout lacks are zero.
+pub(super) fn legacy_particles(encoded: &[u8]) -> Vec<Particle> {
+    bincode::deserialize::<Vec<ParticleV0>>(encoded)
+        .unwrap()
+        .into_iter()
+        .map(|particle| Particle {
+            mass: particle.mass,
+            position: particle.position,
+            z: 0.0,
+            velocity: particle.velocity,
+            color: particle.color,
+            tag: 0,
+            potential: 0.0,
+        })
+        .collect()
 }
@@ -0,0 +1,58 @@
+use super::*;
+use std::{fs, path, sync, thread};
+
+// Snapshot files (`.bin`) in a directory, sorted by name so zero padded frame numbers come out in
+// order.
+pub fn snapshot_files(directory: &str) -> Vec<path::PathBuf> {
+    let mut files: Vec<path::PathBuf> = fs::read_dir(directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "bin"))
+        .collect();
+    files.sort();
+    files
+}
+
+// Renders every snapshot in `snapshot_directory` to `frame_directory/NNNNN.png`, spreading the
+// snapshots over all cores. `camera` gets the index of the snapshot and the loaded world, so it can
+// follow a camera path.
+pub fn render_snapshots<F>(
+    snapshot_directory: &str,
+    frame_directory: &str,
+    world_settings: &WorldSettings,
+    width: u32,
+    height: u32,
+    render_settings: &RenderSettings,
+    camera: F,
+) where
+    F: Fn(usize, &World) -> Camera + Sync,
+{
+    let files = snapshot_files(snapshot_directory);
+    let num_threads = thread::available_parallelism().unwrap().get();
+    let completed = sync::atomic::AtomicUsize::new(0);
+
+    thread::scope(|scope| {
+        for i in 0..num_threads {
+            let files = &files;
+            let camera = &camera;
+            let completed = &completed;
+            scope.spawn(move || {
+                let mut renderer = Renderer::with_settings(width, height, render_settings.clone());
+                let mut world = World::new(world_settings.clone());
+                for j in (i..files.len()).step_by(num_threads) {
+                    world.load_from_file(files[j].to_str().unwrap());
+                    let current_camera = camera(j, &world);
+                    renderer.set_frame(j as u64);
+                    renderer.render(
+                        &world,
+                        &current_camera,
+                        format!("{}/{:05}.png", frame_directory, j).as_str(),
+                    );
+
+                    let done = completed.fetch_add(1, sync::atomic::Ordering::Relaxed) + 1;
+                    println!("{}/{} frames rendered", done, files.len());
+                }
+            });
+        }
+    });
+}
@@ -0,0 +1,304 @@
+use super::*;
+use std::f64::consts::PI;
+use std::fmt::Write;
+use std::fs;
+
+#[derive(Clone, Copy, Debug)]
+pub enum Binning {
+    Linear,
+    // Equal steps in log radius from `inner` out, with the first bin reaching down to the centre.
+    Logarithmic { inner: f64 },
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ProfileSettings {
+    pub r_max: f64,
+    pub num_bins: usize,
+    pub binning: Binning,
+    // Points around each ring where `calculate_gravity` is sampled for the circular speed. Each
+    // sample sums over every particle, so keep this small for large worlds.
+    pub gravity_samples: usize,
+}
+
+impl Default for ProfileSettings {
+    fn default() -> Self {
+        Self {
+            r_max: 25.0,
+            num_bins: 50,
+            binning: Binning::Linear,
+            gravity_samples: 8,
+        }
+    }
+}
+
+// One annulus of a radial profile. Masses, densities and velocities are of the selected particles,
+// except the circular speeds, which come from all the mass in the world.
+#[derive(Clone, Debug)]
+pub struct ProfileBin {
+    pub inner_radius: f64,
+    pub outer_radius: f64,
+    // Middle of the bin, geometric for logarithmic bins. The circular speeds are taken here.
+    pub radius: f64,
+    pub particles: usize,
+    pub mass: f64,
+    pub surface_density: f64,
+    // Selected mass inside the outer radius.
+    pub enclosed_mass: f64,
+    // Softened point mass of everything inside the radius, as in `CircularSpeed::Enclosed`.
+    pub circular_speed_enclosed: f64,
+    // From the azimuthally averaged radial acceleration of `calculate_gravity`.
+    pub circular_speed_gravity: f64,
+    // Mass weighted mean tangential velocity, positive for counterclockwise rotation.
+    pub mean_rotation: f64,
+    pub sigma_radial: f64,
+    pub sigma_tangential: f64,
+    // Surface density of the input distribution, once set with `set_input`.
+    pub input_surface_density: Option<f64>,
+}
+
+#[derive(Clone, Debug)]
+pub struct RadialProfile {
+    pub center: Vector2,
+    // Mean velocity of the selected particles inside `r_max`, which the velocities are relative to.
+    pub center_velocity: Vector2,
+    pub bins: Vec<ProfileBin>,
+}
+
+impl World {
+    // Bins the selected particles into annuli around `center`.
+    pub fn radial_profile<F>(
+        &self,
+        center: Vector2,
+        settings: &ProfileSettings,
+        selection: F,
+    ) -> RadialProfile
+    where
+        F: Fn(&Particle) -> bool,
+    {
+        let edges = bin_edges(settings);
+        let bin_of = |distance: f64| {
+            (edges.partition_point(|edge| *edge <= distance) - 1).min(settings.num_bins - 1)
+        };
+
+        let selected: Vec<(&Particle, f64)> = self
+            .particles
+            .iter()
+            .filter(|particle| selection(particle))
+            .map(|particle| (particle, (particle.position - center).abs()))
+            .filter(|(_, distance)| *distance < settings.r_max)
+            .collect();
+
+        let mut center_velocity = Vector2 { x: 0.0, y: 0.0 };
+        let mut total_mass = 0.0;
+        for (particle, _) in &selected {
+            center_velocity += particle.velocity * particle.mass;
+            total_mass += particle.mass;
+        }
+        if total_mass > 0.0 {
+            center_velocity /= total_mass;
+        }
+
+        // Mass, mass times radial and tangential velocity and their squares per bin.
+        let mut sums = vec![[0.0; 5]; settings.num_bins];
+        let mut counts = vec![0; settings.num_bins];
+        for (particle, distance) in &selected {
+            let bin = bin_of(*distance);
+            let (radial_speed, tangential_speed) = if *distance > 0.0 {
+                let radial = (particle.position - center) / *distance;
+                let velocity = particle.velocity - center_velocity;
+                (velocity.dot(radial), radial.cross(velocity))
+            } else {
+                (0.0, 0.0)
+            };
+            let mass = particle.mass;
+            let sum = &mut sums[bin];
+            sum[0] += mass;
+            sum[1] += mass * radial_speed;
+            sum[2] += mass * radial_speed * radial_speed;
+            sum[3] += mass * tangential_speed;
+            sum[4] += mass * tangential_speed * tangential_speed;
+            counts[bin] += 1;
+        }
+
+        let mut all_radii: Vec<(f64, f64)> = self
+            .particles
+            .iter()
+            .map(|particle| ((particle.position - center).abs(), particle.mass))
+            .collect();
+        all_radii.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let mut all_enclosed = vec![0.0];
+        for (_, mass) in &all_radii {
+            all_enclosed.push(all_enclosed.last().unwrap() + mass);
+        }
+
+        let mut bins = vec![];
+        let mut enclosed_mass = 0.0;
+        for b in 0..settings.num_bins {
+            let (inner_radius, outer_radius) = (edges[b], edges[b + 1]);
+            let radius = match settings.binning {
+                Binning::Logarithmic { .. } if b > 0 => (inner_radius * outer_radius).sqrt(),
+                _ => (inner_radius + outer_radius) / 2.0,
+            };
+            let [mass, radial, radial_squared, tangential, tangential_squared] = sums[b];
+            enclosed_mass += mass;
+
+            let area = PI * (outer_radius * outer_radius - inner_radius * inner_radius);
+            let dispersion = |sum: f64, sum_squared: f64| {
+                let mean = sum / mass;
+                (sum_squared / mass - mean * mean).max(0.0).sqrt()
+            };
+            let (mean_rotation, sigma_radial, sigma_tangential) = if mass > 0.0 {
+                (
+                    tangential / mass,
+                    dispersion(radial, radial_squared),
+                    dispersion(tangential, tangential_squared),
+                )
+            } else {
+                (0.0, 0.0, 0.0)
+            };
+
+            let inside = all_radii.partition_point(|(r, _)| *r < radius);
+            let softening = self.settings.softening_length;
+            let circular_speed_enclosed =
+                (self.settings.gravity_strength * all_enclosed[inside] * radius
+                    / (radius * radius + softening * softening))
+                    .sqrt();
+
+            bins.push(ProfileBin {
+                inner_radius,
+                outer_radius,
+                radius,
+                particles: counts[b],
+                mass,
+                surface_density: mass / area,
+                enclosed_mass,
+                circular_speed_enclosed,
+                circular_speed_gravity: self.ring_circular_speed(
+                    center,
+                    radius,
+                    settings.gravity_samples,
+                ),
+                mean_rotation,
+                sigma_radial,
+                sigma_tangential,
+                input_surface_density: None,
+            });
+        }
+
+        RadialProfile {
+            center,
+            center_velocity,
+            bins,
+        }
+    }
+
+    // sqrt(r <-a·r̂>) averaged over `samples` points evenly spaced around the ring.
+    fn ring_circular_speed(&self, center: Vector2, radius: f64, samples: usize) -> f64 {
+        if samples == 0 || radius == 0.0 {
+            return 0.0;
+        }
+        let mut radial_acceleration = 0.0;
+        for i in 0..samples {
+            let angle = 2.0 * PI * (i as f64 + 0.5) / samples as f64;
+            let direction = Vector2 {
+                x: angle.cos(),
+                y: angle.sin(),
+            };
+            let acceleration = self.calculate_gravity(center + direction * radius);
+            radial_acceleration -= acceleration.dot(direction);
+        }
+        (radial_acceleration / samples as f64 * radius)
+            .max(0.0)
+            .sqrt()
+    }
+}
+
+impl RadialProfile {
+    // Fills in `input_surface_density` from a density function as used by `from_distrobution`,
+    // integrated over |z| < z_max.
+    pub fn set_input(&mut self, density_fn: fn(radius: f64, z: f64) -> f64, z_max: f64) {
+        for bin in &mut self.bins {
+            bin.input_surface_density = Some(distrobution_surface_density(
+                density_fn,
+                bin.inner_radius,
+                bin.outer_radius,
+                z_max,
+                20,
+                100,
+            ));
+        }
+    }
+
+    // One row per bin. The input column is left empty until `set_input` is called.
+    pub fn save_csv(&self, path: &str) {
+        let mut csv = String::from(
+            "inner_radius,outer_radius,radius,particles,mass,surface_density,input_surface_density,\
+             enclosed_mass,circular_speed_enclosed,circular_speed_gravity,mean_rotation,\
+             sigma_radial,sigma_tangential\n",
+        );
+        for bin in &self.bins {
+            let input = bin
+                .input_surface_density
+                .map_or(String::new(), |density| density.to_string());
+            writeln!(
+                csv,
+                "{},{},{},{},{},{},{},{},{},{},{},{},{}",
+                bin.inner_radius,
+                bin.outer_radius,
+                bin.radius,
+                bin.particles,
+                bin.mass,
+                bin.surface_density,
+                input,
+                bin.enclosed_mass,
+                bin.circular_speed_enclosed,
+                bin.circular_speed_gravity,
+                bin.mean_rotation,
+                bin.sigma_radial,
+                bin.sigma_tangential
+            )
+            .unwrap();
+        }
+        fs::write(path, csv).unwrap();
+    }
+
+    // Aligned table of the main columns, for a quick look in the terminal.
+    pub fn print(&self) {
+        println!(
+            "{:>8} {:>8} {:>11} {:>11} {:>11} {:>9} {:>9} {:>9} {:>9}",
+            "radius", "count", "sigma", "input", "M(<r)", "vc_mass", "vc_grav", "v_rot", "sigma_r"
+        );
+        for bin in &self.bins {
+            let input = bin
+                .input_surface_density
+                .map_or("-".to_string(), |density| format!("{:.4e}", density));
+            println!(
+                "{:>8.3} {:>8} {:>11.4e} {:>11} {:>11.4e} {:>9.4} {:>9.4} {:>9.4} {:>9.4}",
+                bin.radius,
+                bin.particles,
+                bin.surface_density,
+                input,
+                bin.enclosed_mass,
+                bin.circular_speed_enclosed,
+                bin.circular_speed_gravity,
+                bin.mean_rotation,
+                bin.sigma_radial
+            );
+        }
+    }
+}
+
+fn bin_edges(settings: &ProfileSettings) -> Vec<f64> {
+    let num_bins = settings.num_bins;
+    match settings.binning {
+        Binning::Linear => (0..=num_bins)
+            .map(|b| settings.r_max * b as f64 / num_bins as f64)
+            .collect(),
+        Binning::Logarithmic { inner } => {
+            let ratio = (settings.r_max / inner).powf(1.0 / (num_bins - 1) as f64);
+            let mut edges = vec![0.0];
+            edges.extend((0..num_bins).map(|b| inner * ratio.powi(b as i32)));
+            edges
+        }
+    }
+}
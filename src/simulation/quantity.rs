@@ -0,0 +1,126 @@
+use super::*;
+use std::f64::consts::PI;
+
+// Per particle scalars. Velocities and positions are taken in the centre of mass frame.
+#[derive(Clone, Copy, Debug)]
+pub enum Quantity {
+    Speed,
+    RadialVelocity,
+    // Kinetic plus potential energy per unit mass.
+    SpecificEnergy,
+    // Softened potential per unit mass, computed fresh like the forces.
+    Potential,
+    // The potential stored in the particles, e.g. from a snapshot saved after
+    // `World::update_potentials`.
+    StoredPotential,
+    // z component of the specific angular momentum, positive for counterclockwise orbits.
+    AngularMomentum,
+    // Surface density from the mass of the k nearest neighbours.
+    LocalDensity { neighbours: usize },
+    // Index of the particle in the world.
+    Id,
+    // The particle tag.
+    Species,
+}
+
+impl Quantity {
+    pub fn values(&self, world: &World) -> Vec<f64> {
+        let center = world.center_of_mass();
+        let center_velocity = world.center_of_mass_velocity();
+        let relative = |particle: &Particle| {
+            (
+                particle.position - center,
+                particle.velocity - center_velocity,
+            )
+        };
+
+        match self {
+            Quantity::Speed => world
+                .particles
+                .iter()
+                .map(|particle| relative(particle).1.abs())
+                .collect(),
+            Quantity::RadialVelocity => world
+                .particles
+                .iter()
+                .map(|particle| {
+                    let (position, velocity) = relative(particle);
+                    let distance = position.abs();
+                    if distance > 0.0 {
+                        velocity.dot(position) / distance
+                    } else {
+                        0.0
+                    }
+                })
+                .collect(),
+            Quantity::SpecificEnergy => {
+                let potentials = world.calculate_potentials();
+                world
+                    .particles
+                    .iter()
+                    .zip(potentials)
+                    .map(|(particle, potential)| {
+                        let speed = relative(particle).1.abs();
+                        0.5 * speed * speed + potential
+                    })
+                    .collect()
+            }
+            Quantity::Potential => world.calculate_potentials(),
+            Quantity::StoredPotential => world
+                .particles
+                .iter()
+                .map(|particle| particle.potential)
+                .collect(),
+            Quantity::AngularMomentum => world
+                .particles
+                .iter()
+                .map(|particle| {
+                    let (position, velocity) = relative(particle);
+                    position.cross(velocity)
+                })
+                .collect(),
+            Quantity::LocalDensity { neighbours } => {
+                let tree = KdTree::from_world(world);
+                world
+                    .particles
+                    .iter()
+                    .map(|particle| {
+                        let nearest = tree.nearest(particle.position, neighbours + 1);
+                        let radius = nearest.last().map_or(0.0, |(_, distance)| *distance);
+                        let mass: f64 = nearest
+                            .iter()
+                            .map(|(index, _)| world.particles[*index].mass)
+                            .sum();
+                        if radius > 0.0 {
+                            mass / (PI * radius * radius)
+                        } else {
+                            0.0
+                        }
+                    })
+                    .collect()
+            }
+            Quantity::Id => (0..world.particles.len()).map(|i| i as f64).collect(),
+            Quantity::Species => world
+                .particles
+                .iter()
+                .map(|particle| particle.tag as f64)
+                .collect(),
+        }
+    }
+}
+
+// The 1st and 99th percentile, so single outliers such as a central black hole do not squash the
+// colormap.
+pub fn quantity_range(values: &[f64]) -> (f64, f64) {
+    let mut sorted: Vec<f64> = values
+        .iter()
+        .cloned()
+        .filter(|value| value.is_finite())
+        .collect();
+    if sorted.is_empty() {
+        return (0.0, 1.0);
+    }
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let percentile = |p: f64| sorted[(p * (sorted.len() - 1) as f64).round() as usize];
+    (percentile(0.01), percentile(0.99))
+}
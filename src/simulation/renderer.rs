@@ -1,79 +1,1081 @@
 use super::*;
+use std::{fs, thread};
 
-pub struct Camera {
-    pub position: Vector2,
-    pub zoom: f64,
-    pub brightness: f64,
+#[derive(Clone, Copy, Debug)]
+pub enum Deposition {
+    // Each particle lands in the single pixel it falls in.
+    Point,
+    // Cloud in cell: each particle is shared between the four nearest pixel centres, so sub-pixel
+    // positions are kept.
+    Bilinear,
+    // Each particle is spread over a smoothing kernel, normalised so it still adds its full color.
+    Kernel { kernel: Kernel, size: KernelSize },
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Kernel {
+    // Standard deviation of half the smoothing length, cut off at three standard deviations.
+    Gaussian,
+    // M4 cubic spline reaching zero at the smoothing length.
+    CubicSpline,
+}
+
+// Smoothing lengths are in world units.
+#[derive(Clone, Copy, Debug)]
+pub enum KernelSize {
+    Fixed(f64),
+    // Smoothing length times the square root of the particle mass.
+    MassScaled(f64),
+    // Scale times the distance to the k-th nearest neighbour.
+    Adaptive { neighbours: usize, scale: f64 },
+}
+
+#[derive(Clone, Debug)]
+pub enum ColorMode {
+    // Sum of the particle colors, tone mapped per channel.
+    ParticleColor,
+    // Tone mapped density (in units of the mean particle mass) through a colormap.
+    Density(Colormap),
+    // Every particle gets the colormap color of its value, then summed like particle colors.
+    // Without a range the 1st to 99th percentile of the values is used.
+    Quantity {
+        quantity: Quantity,
+        colormap: Colormap,
+        range: Option<(f64, f64)>,
+    },
+    // Mass weighted mean of the value in each pixel through the colormap, with the tone mapped
+    // density as brightness.
+    MassWeighted {
+        quantity: Quantity,
+        colormap: Colormap,
+        range: Option<(f64, f64)>,
+    },
+}
+
+// Motion effects. Trails rely on the renderer seeing consecutive frames, so they do not work with
+// `render_snapshots`, which spreads the frames over several renderers.
+#[derive(Clone, Copy, Debug)]
+pub enum Motion {
+    None,
+    // The previous frames fade in with their buffers multiplied by `decay` (0 to 1) every frame.
+    Trails { decay: f64 },
+    // Every particle is smeared over where it was `exposure` time units ago at its current
+    // velocity.
+    Streaks { exposure: f64 },
+}
+
+// How the supersampled image is reduced to the output resolution. Widths are in output pixels.
+#[derive(Clone, Copy, Debug)]
+pub enum DownsampleFilter {
+    // Plain average of the samples inside the pixel.
+    Box,
+    // Triangle reaching zero one pixel from the centre.
+    Tent,
+    // Standard deviation of half a pixel, cut off at 1.5 pixels.
+    Gaussian,
+}
+
+#[derive(Clone, Debug)]
+pub struct RenderSettings {
+    pub deposition: Deposition,
+    pub tone_mapper: ToneMapper,
+    pub color_mode: ColorMode,
+    // Accumulates at this many times the resolution in each direction and filters down before tone
+    // mapping. 1 disables supersampling.
+    pub supersampling: u32,
+    pub downsample_filter: DownsampleFilter,
+    pub motion: Motion,
+    pub overlays: Vec<Overlay>,
+    // Spreads the deposition and tone mapping over all cores, like
+    // `WorldSettings::multiprocessing`.
+    pub multiprocessing: bool,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            deposition: Deposition::Point,
+            tone_mapper: ToneMapper::Log,
+            color_mode: ColorMode::ParticleColor,
+            supersampling: 1,
+            downsample_filter: DownsampleFilter::Box,
+            motion: Motion::None,
+            overlays: vec![],
+            multiprocessing: false,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Sample {
+    color: (f64, f64, f64),
+    density: f64,
+    value: f64,
+}
+
+impl Sample {
+    fn scaled(&self, weight: f64) -> Sample {
+        Sample {
+            color: (
+                self.color.0 * weight,
+                self.color.1 * weight,
+                self.color.2 * weight,
+            ),
+            density: self.density * weight,
+            value: self.value,
+        }
+    }
+}
+
+// A particle projected to sample pixels.
+#[derive(Clone, Copy)]
+struct Splat {
+    position: Vector2,
+    // Where the streak starts, when drawing streaks.
+    streak_start: Option<Vector2>,
+    // In pixels, only used by kernel deposition.
+    smoothing_length: f64,
+    sample: Sample,
+}
+
+// A rectangle of pixels with its top left corner at (x, y).
+#[derive(Clone, Copy, PartialEq)]
+struct Rect {
+    x: i64,
+    y: i64,
+    width: i64,
+    height: i64,
+}
+
+impl Rect {
+    fn len(&self) -> usize {
+        (self.width * self.height) as usize
+    }
+
+    fn contains(&self, x: i64, y: i64) -> bool {
+        x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+    }
+
+    fn index(&self, x: i64, y: i64) -> usize {
+        ((y - self.y) * self.width + x - self.x) as usize
+    }
+
+    // Whether the square of half width `reach` around each point of the segment touches the
+    // rectangle.
+    fn touches(&self, from: Vector2, to: Vector2, reach: f64) -> bool {
+        from.x.max(to.x) + reach >= self.x as f64
+            && from.x.min(to.x) - reach < (self.x + self.width) as f64
+            && from.y.max(to.y) + reach >= self.y as f64
+            && from.y.min(to.y) - reach < (self.y + self.height) as f64
+    }
+}
+
+// The accumulation buffers over a rectangle of sample pixels, so every thread can fill its own
+// rows. Positions are in sample pixels of the whole frame.
+struct Accumulator<'a> {
+    rect: Rect,
+    color: &'a mut [(f64, f64, f64)],
+    density: &'a mut [f64],
+    quantity: &'a mut [f64],
+}
+
+impl Accumulator<'_> {
+    // `max_support` caps the kernel size, so a particle right in front of the camera does not
+    // loop over millions of pixels.
+    fn splat(&mut self, splat: &Splat, deposition: Deposition, max_support: f64) {
+        let reach = match deposition {
+            Deposition::Point | Deposition::Bilinear => 1.0,
+            Deposition::Kernel { kernel, .. } => {
+                kernel_support(kernel, splat.smoothing_length).min(max_support) + 1.0
+            }
+        };
+        let start = splat.streak_start.unwrap_or(splat.position);
+        // Also false for NaN positions.
+        if !self.rect.touches(start, splat.position, reach) {
+            return;
+        }
+
+        match splat.streak_start {
+            Some(start) => {
+                // About one deposit per pixel along the streak, capped for particles racing
+                // across the whole screen.
+                let length = (splat.position - start).abs();
+                let steps = if length.is_finite() {
+                    (length.ceil() as usize).clamp(1, 4 * max_support as usize)
+                } else {
+                    1
+                };
+                let part = splat.sample.scaled(1.0 / steps as f64);
+                for step in 0..steps {
+                    let t = (step as f64 + 0.5) / steps as f64;
+                    let position = start + (splat.position - start) * t;
+                    self.deposit(
+                        position,
+                        splat.smoothing_length,
+                        &part,
+                        deposition,
+                        max_support,
+                    );
+                }
+            }
+            None => self.deposit(
+                splat.position,
+                splat.smoothing_length,
+                &splat.sample,
+                deposition,
+                max_support,
+            ),
+        }
+    }
+
+    fn add_to_pixel(&mut self, x: i64, y: i64, sample: &Sample, weight: f64) {
+        if !self.rect.contains(x, y) {
+            return;
+        }
+        let i = self.rect.index(x, y);
+        self.color[i].0 += sample.color.0 * weight;
+        self.color[i].1 += sample.color.1 * weight;
+        self.color[i].2 += sample.color.2 * weight;
+        self.density[i] += sample.density * weight;
+        self.quantity[i] += sample.density * sample.value * weight;
+    }
+
+    fn deposit(
+        &mut self,
+        screen_pos: Vector2,
+        smoothing_length: f64,
+        sample: &Sample,
+        deposition: Deposition,
+        max_support: f64,
+    ) {
+        match deposition {
+            Deposition::Point => self.deposit_point(screen_pos, sample),
+            Deposition::Bilinear => self.deposit_bilinear(screen_pos, sample),
+            Deposition::Kernel { kernel, .. } => {
+                self.deposit_kernel(screen_pos, smoothing_length, kernel, sample, max_support)
+            }
+        }
+    }
+
+    fn deposit_point(&mut self, screen_pos: Vector2, sample: &Sample) {
+        if !(screen_pos.x >= 0.0 && screen_pos.y >= 0.0) {
+            return;
+        }
+        self.add_to_pixel(screen_pos.x as i64, screen_pos.y as i64, sample, 1.0);
+    }
+
+    fn deposit_bilinear(&mut self, screen_pos: Vector2, sample: &Sample) {
+        // Pixel centres are at half integers.
+        let x = screen_pos.x - 0.5;
+        let y = screen_pos.y - 0.5;
+        if !(x.is_finite() && y.is_finite()) {
+            return;
+        }
+        let x0 = x.floor();
+        let y0 = y.floor();
+        let tx = x - x0;
+        let ty = y - y0;
+
+        for (dy, weight_y) in [(0, 1.0 - ty), (1, ty)] {
+            for (dx, weight_x) in [(0, 1.0 - tx), (1, tx)] {
+                let weight = weight_x * weight_y;
+                if weight > 0.0 {
+                    self.add_to_pixel(x0 as i64 + dx, y0 as i64 + dy, sample, weight);
+                }
+            }
+        }
+    }
+
+    // `smoothing_length` is in pixels. Kernels too small to cover a pixel centre fall back to a
+    // point deposit so no particle is lost.
+    fn deposit_kernel(
+        &mut self,
+        screen_pos: Vector2,
+        smoothing_length: f64,
+        kernel: Kernel,
+        sample: &Sample,
+        max_support: f64,
+    ) {
+        let support = kernel_support(kernel, smoothing_length).min(max_support);
+
+        // The weights are normalised over the whole footprint, so particles near the edge only
+        // lose the part that falls outside the image.
+        let min_x = (screen_pos.x - support).floor() as i64;
+        let max_x = (screen_pos.x + support).ceil() as i64;
+        let min_y = (screen_pos.y - support).floor() as i64;
+        let max_y = (screen_pos.y + support).ceil() as i64;
+        let rect = self.rect;
+        if max_x < rect.x
+            || max_y < rect.y
+            || min_x >= rect.x + rect.width
+            || min_y >= rect.y + rect.height
+        {
+            return;
+        }
+
+        let weight = |x: i64, y: i64| {
+            let pixel_center = Vector2 {
+                x: x as f64 + 0.5,
+                y: y as f64 + 0.5,
+            };
+            let q = (pixel_center - screen_pos).abs() / smoothing_length;
+            kernel_weight(kernel, q)
+        };
+
+        let mut total_weight = 0.0;
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                total_weight += weight(x, y);
+            }
+        }
+        if total_weight == 0.0 {
+            self.deposit_point(screen_pos, sample);
+            return;
+        }
+
+        for y in min_y.max(rect.y)..max_y.min(rect.y + rect.height) {
+            for x in min_x.max(rect.x)..max_x.min(rect.x + rect.width) {
+                let w = weight(x, y) / total_weight;
+                if w > 0.0 {
+                    self.add_to_pixel(x, y, sample, w);
+                }
+            }
+        }
+    }
 }
 
 pub struct Renderer {
     width: u32,
     height: u32,
+    // Resolution of the accumulation buffers, larger than the output when supersampling.
+    sample_width: u32,
+    sample_height: u32,
+    pub settings: RenderSettings,
     img_buffer: image::ImageBuffer<image::Rgb<u8>, Vec<u8>>,
+    // The sample pixels held by the buffers. The whole frame, except during a tiled render.
+    region: Rect,
     color_buffer: Vec<(f64, f64, f64)>,
+    density_buffer: Vec<f64>,
+    quantity_buffer: Vec<f64>,
+    brightness: f64,
+    range: (f64, f64),
+    // Number of the next frame, for the frame counter overlay.
+    frame: u64,
 }
 
 impl Renderer {
     pub fn new(width: u32, height: u32) -> Self {
-        let mut color_buffer = Vec::new();
-        color_buffer.resize((width * height) as usize, (0.0, 0.0, 0.0));
+        Self::with_settings(width, height, RenderSettings::default())
+    }
+
+    // The float buffers are only allocated by the first render, so posters rendered with
+    // `render_tiled` never hold them for the whole frame.
+    pub fn with_settings(width: u32, height: u32, settings: RenderSettings) -> Self {
         Self {
             width,
             height,
+            sample_width: width,
+            sample_height: height,
+            settings,
             img_buffer: image::ImageBuffer::new(width, height),
-            color_buffer,
+            region: Rect {
+                x: 0,
+                y: 0,
+                width: 0,
+                height: 0,
+            },
+            color_buffer: vec![],
+            density_buffer: vec![],
+            quantity_buffer: vec![],
+            brightness: 1.0,
+            range: (0.0, 1.0),
+            frame: 0,
         }
     }
 
     pub fn render(&mut self, world: &World, camera: &Camera, filepath: &str) {
+        self.render_frame(world, camera);
+        self.save(filepath);
+    }
+
+    pub fn save(&self, filepath: &str) {
+        self.img_buffer.save(filepath).unwrap();
+    }
+
+    pub fn image(&self) -> &image::RgbImage {
+        &self.img_buffer
+    }
+
+    // Renders into the internal buffers without writing anything, for the `save` functions.
+    pub fn render_frame(&mut self, world: &World, camera: &Camera) {
+        self.render_frame_3d(world, &Camera3D::from(*camera));
+    }
+
+    pub fn render_3d(&mut self, world: &World, camera: &Camera3D, filepath: &str) {
+        self.render_frame_3d(world, camera);
+        self.save(filepath);
+    }
+
+    // Like `render_frame`, but through a 3D camera using `Particle::z` as the third coordinate.
+    pub fn render_frame_3d(&mut self, world: &World, camera: &Camera3D) {
+        self.set_sample_size();
+        let region = Rect {
+            x: 0,
+            y: 0,
+            width: self.sample_width as i64,
+            height: self.sample_height as i64,
+        };
+        let resized = self.region != region;
+        self.set_region(region);
+
         self.img_buffer.fill(0);
+        if let (Motion::Trails { decay }, false) = (self.settings.motion, resized) {
+            for color in self.color_buffer.iter_mut() {
+                *color = (color.0 * decay, color.1 * decay, color.2 * decay);
+            }
+            self.density_buffer
+                .iter_mut()
+                .for_each(|density| *density *= decay);
+            self.quantity_buffer
+                .iter_mut()
+                .for_each(|quantity| *quantity *= decay);
+        } else {
+            self.clear_buffers();
+        }
+
+        let (splats, range) = self.splats(world, camera);
+        self.accumulate(&splats);
+
+        self.brightness = camera.brightness;
+        self.range = range;
+        self.tone_map(self.output_rect(), None);
+        self.draw_overlays(world, camera);
+    }
+
+    // Renders the frame one tile of at most `tile_size` squared pixels at a time, so the float
+    // buffers never cover more than a tile. For posters too large for `render_frame`. Afterwards
+    // only the 8 bit image is available. Auto exposure takes an extra pass over the tiles.
+    pub fn render_tiled(&mut self, world: &World, camera: &Camera3D, tile_size: u32) {
+        self.set_sample_size();
+        self.img_buffer.fill(0);
+        let (splats, range) = self.splats(world, camera);
+        self.brightness = camera.brightness;
+        self.range = range;
+
+        // Supersampled tiles need the samples around them for the downsample filter.
+        let factor = (self.sample_width / self.width) as i64;
+        let apron = if factor > 1 { 2 * factor } else { 0 };
+        let mut tiles = vec![];
+        for tile_y in (0..self.height).step_by(tile_size as usize) {
+            for tile_x in (0..self.width).step_by(tile_size as usize) {
+                let output = Rect {
+                    x: tile_x as i64,
+                    y: tile_y as i64,
+                    width: tile_size.min(self.width - tile_x) as i64,
+                    height: tile_size.min(self.height - tile_y) as i64,
+                };
+                let min_x = (output.x * factor - apron).max(0);
+                let min_y = (output.y * factor - apron).max(0);
+                let max_x =
+                    ((output.x + output.width) * factor + apron).min(self.sample_width as i64);
+                let max_y =
+                    ((output.y + output.height) * factor + apron).min(self.sample_height as i64);
+                let region = Rect {
+                    x: min_x,
+                    y: min_y,
+                    width: max_x - min_x,
+                    height: max_y - min_y,
+                };
+                tiles.push((output, region));
+            }
+        }
+
+        let exposure = self.tiled_exposure(&splats, &tiles);
+        for (output, region) in tiles {
+            self.set_region(region);
+            self.clear_buffers();
+            self.accumulate(&splats);
+            self.tone_map(output, exposure);
+        }
+        self.draw_overlays(world, camera);
+    }
+
+    // Sets the number of the next frame, for renderers that do not see every frame.
+    pub fn set_frame(&mut self, frame: u64) {
+        self.frame = frame;
+    }
+
+    pub(super) fn image_mut(&mut self) -> &mut image::RgbImage {
+        &mut self.img_buffer
+    }
+
+    // Output pixel position of a point in the last rendered frame, or None if it is behind a
+    // perspective camera. For drawing on top of a render.
+    pub(super) fn world_to_image(&self, position: Vector3, camera: &Camera3D) -> Option<Vector2> {
+        let factor = (self.sample_width / self.width) as f64;
+        self.project(position, camera, camera.basis())
+            .map(|(screen_pos, _, _)| screen_pos / factor)
+    }
+
+    // The supersampling can change between renders.
+    fn set_sample_size(&mut self) {
+        let factor = self.settings.supersampling.max(1);
+        self.sample_width = self.width * factor;
+        self.sample_height = self.height * factor;
+    }
+
+    fn set_region(&mut self, region: Rect) {
+        self.region = region;
+        self.color_buffer.resize(region.len(), (0.0, 0.0, 0.0));
+        self.density_buffer.resize(region.len(), 0.0);
+        self.quantity_buffer.resize(region.len(), 0.0);
+    }
+
+    pub(super) fn clear_buffers(&mut self) {
         self.color_buffer.fill((0.0, 0.0, 0.0));
+        self.density_buffer.fill(0.0);
+        self.quantity_buffer.fill(0.0);
+    }
+
+    fn output_rect(&self) -> Rect {
+        Rect {
+            x: 0,
+            y: 0,
+            width: self.width as i64,
+            height: self.height as i64,
+        }
+    }
+
+    fn threads(&self) -> usize {
+        if self.settings.multiprocessing {
+            thread::available_parallelism().unwrap().get()
+        } else {
+            1
+        }
+    }
+
+    // Every particle projected to the screen, and the range of the colored quantity if any.
+    fn splats(&self, world: &World, camera: &Camera3D) -> (Vec<Splat>, (f64, f64)) {
+        let (samples, range) = self.samples(world);
+        let smoothing_lengths = match self.settings.deposition {
+            Deposition::Kernel { size, .. } => smoothing_lengths(world, size),
+            _ => vec![0.0; world.particles.len()],
+        };
+        let basis = camera.basis();
+        let splats = parallel_collect(world.particles.len(), self.threads(), |i| {
+            let particle = &world.particles[i];
+            let (position, scale, depth) = self.project(particle.position_3d(), camera, basis)?;
+            let cue = camera.depth_cue.weight(depth);
+            if cue <= 0.0 {
+                return None;
+            }
+            let streak_start = match self.settings.motion {
+                Motion::Streaks { exposure } => {
+                    let start =
+                        particle.position_3d() - Vector3::from(particle.velocity) * exposure;
+                    self.project(start, camera, basis)
+                        .map(|(start, _, _)| start)
+                }
+                _ => None,
+            };
+            Some(Splat {
+                position,
+                streak_start,
+                smoothing_length: smoothing_lengths[i] * scale,
+                sample: samples[i].scaled(cue),
+            })
+        });
+        (splats.into_iter().flatten().collect(), range)
+    }
+
+    // Deposits the splats into the buffers, with every thread filling its own band of rows.
+    fn accumulate(&mut self, splats: &[Splat]) {
+        let region = self.region;
+        if region.len() == 0 {
+            return;
+        }
+        let deposition = self.settings.deposition;
+        let max_support = self.sample_width.max(self.sample_height) as f64;
+        let rows = (region.height as usize).div_ceil(self.threads());
+        let band = rows * region.width as usize;
+
+        let bands = self
+            .color_buffer
+            .chunks_mut(band)
+            .zip(self.density_buffer.chunks_mut(band))
+            .zip(self.quantity_buffer.chunks_mut(band))
+            .enumerate();
+        thread::scope(|scope| {
+            for (i, ((color, density), quantity)) in bands {
+                let mut accumulator = Accumulator {
+                    rect: Rect {
+                        x: region.x,
+                        y: region.y + (i * rows) as i64,
+                        width: region.width,
+                        height: color.len() as i64 / region.width,
+                    },
+                    color,
+                    density,
+                    quantity,
+                };
+                scope.spawn(move || {
+                    for splat in splats {
+                        accumulator.splat(splat, deposition, max_support);
+                    }
+                });
+            }
+        });
+    }
 
-        for particle in &world.particles {
-            let screen_pos = self.vector_world_to_screen(particle.position, camera);
-            if screen_pos.x < 0.0
-                || screen_pos.x >= self.width as f64
-                || screen_pos.y < 0.0
-                || screen_pos.y >= self.height as f64
-            {
-                continue;
+    // Auto exposure needs the whole frame, so tiled renders first go over the tiles collecting a
+    // histogram of the pixel values, with 64 bins per doubling.
+    fn tiled_exposure(&mut self, splats: &[Splat], tiles: &[(Rect, Rect)]) -> Option<f64> {
+        let ToneMapper::AutoExposure { percentile } = self.settings.tone_mapper else {
+            return None;
+        };
+        let bin = |value: f64| ((value.log2() + 64.0) * 64.0).clamp(0.0, 128.0 * 64.0 - 1.0);
+        let mut histogram = vec![0u64; 128 * 64];
+        for (output, region) in tiles {
+            self.set_region(*region);
+            self.clear_buffers();
+            self.accumulate(splats);
+            for value in self.exposure_values(*output) {
+                if value > 0.0 {
+                    histogram[bin(value) as usize] += 1;
+                }
             }
-            let x = screen_pos.x as usize;
-            let y = screen_pos.y as usize;
-            let i = y * (self.width as usize) + x;
+        }
 
-            self.color_buffer[i].0 += particle.color.0;
-            self.color_buffer[i].1 += particle.color.1;
-            self.color_buffer[i].2 += particle.color.2;
+        let total: u64 = histogram.iter().sum();
+        if total == 0 {
+            return Some(1.0);
         }
+        let rank = (percentile / 100.0 * (total - 1) as f64).round() as u64;
+        let mut count = 0;
+        for (i, bin_count) in histogram.iter().enumerate() {
+            count += bin_count;
+            if count > rank {
+                return Some(2f64.powf((i as f64 + 0.5) / 64.0 - 64.0));
+            }
+        }
+        Some(1.0)
+    }
 
-        for y in 0..self.height {
-            for x in 0..self.width {
-                let i = (y * self.width + x) as usize;
+    // What auto exposure looks at for the output pixels in `output`.
+    fn exposure_values(&self, output: Rect) -> Vec<f64> {
+        match &self.settings.color_mode {
+            ColorMode::ParticleColor | ColorMode::Quantity { .. } => self
+                .downsample(&self.color_buffer, output, (0.0, 0.0, 0.0), add_color)
+                .iter()
+                .map(|color| color.0.max(color.1).max(color.2))
+                .collect(),
+            _ => self.downsample(&self.density_buffer, output, 0.0, add_scalar),
+        }
+    }
+
+    fn draw_overlays(&mut self, world: &World, camera: &Camera3D) {
+        // With perspective the scale bar holds at the depth of the centre of mass.
+        let center_depth =
+            (Vector3::from(world.center_of_mass()) - camera.position).dot(camera.basis().2);
+        let pixels_per_unit =
+            self.screen_scale(camera, center_depth) * self.width as f64 / self.sample_width as f64;
+        let info = FrameInfo {
+            pixels_per_unit,
+            time: world.time,
+            frame: self.frame,
+            color_mode: &self.settings.color_mode,
+            range: self.range,
+        };
+        draw_overlays(&mut self.img_buffer, &self.settings.overlays, &info);
+        self.frame += 1;
+    }
 
-                /*let r = (self.color_buffer[i].0 * camera.brightness * 255.0) as u8;
-                let g = (self.color_buffer[i].1 * camera.brightness * 255.0) as u8;
-                let b = (self.color_buffer[i].2 * camera.brightness * 255.0) as u8;*/
-                let r = ((self.color_buffer[i].0 + 1.0).log2() * camera.brightness * 255.0) as u8;
-                let g = ((self.color_buffer[i].1 + 1.0).log2() * camera.brightness * 255.0) as u8;
-                let b = ((self.color_buffer[i].2 + 1.0).log2() * camera.brightness * 255.0) as u8;
-                self.img_buffer
-                    .put_pixel(x, y, image::Rgb::<u8> { 0: [r, g, b] });
+    // What every particle adds to the buffers, and the range of the colored quantity if any.
+    fn samples(&self, world: &World) -> (Vec<Sample>, (f64, f64)) {
+        let mean_mass = world.total_mass() / world.particles.len() as f64;
+        // Supersampled pixels are smaller, so scale them up to keep the brightness per output
+        // pixel.
+        let area = (self.sample_width / self.width).pow(2) as f64;
+        let (values, range) = match &self.settings.color_mode {
+            ColorMode::Quantity {
+                quantity, range, ..
             }
+            | ColorMode::MassWeighted {
+                quantity, range, ..
+            } => {
+                let values = quantity.values(world);
+                let range = range.unwrap_or_else(|| quantity_range(&values));
+                (values, range)
+            }
+            _ => (vec![0.0; world.particles.len()], (0.0, 1.0)),
+        };
+
+        let samples = world
+            .particles
+            .iter()
+            .zip(values)
+            .map(|(particle, value)| {
+                let color = match &self.settings.color_mode {
+                    ColorMode::Quantity { colormap, .. } => colormap.color(normalize(value, range)),
+                    _ => particle.color,
+                };
+                Sample {
+                    color: (color.0 * area, color.1 * area, color.2 * area),
+                    density: particle.mass / mean_mass * area,
+                    value,
+                }
+            })
+            .collect();
+        (samples, range)
+    }
+
+    // Tone maps the output pixels in `output` into the image. Without an exposure it is taken from
+    // the buffers.
+    fn tone_map(&mut self, output: Rect, exposure: Option<f64>) {
+        let pixels = self.mapped_pixels(output, exposure);
+        let to_u8 = |value: f64| (value * 255.0) as u8;
+        for (i, color) in pixels.into_iter().enumerate() {
+            let x = output.x as u32 + (i as i64 % output.width) as u32;
+            let y = output.y as u32 + (i as i64 / output.width) as u32;
+            let pixel = [to_u8(color.0), to_u8(color.1), to_u8(color.2)];
+            self.img_buffer.put_pixel(x, y, image::Rgb(pixel));
+        }
+    }
+
+    // The float outputs need the buffers of the whole frame, which a tiled render does not keep.
+    fn check_whole_frame(&self) {
+        let whole = Rect {
+            x: 0,
+            y: 0,
+            width: self.sample_width as i64,
+            height: self.sample_height as i64,
+        };
+        if self.region != whole {
+            panic!("Float outputs are only available after render_frame");
         }
+    }
+
+    // The raw accumulated colors of the last render at the output resolution.
+    fn output_colors(&self) -> Vec<(f64, f64, f64)> {
+        self.check_whole_frame();
+        self.downsample(
+            &self.color_buffer,
+            self.output_rect(),
+            (0.0, 0.0, 0.0),
+            add_color,
+        )
+    }
+
+    // The density of the last render at the output resolution.
+    fn output_densities(&self) -> Vec<f64> {
+        self.check_whole_frame();
+        self.downsample(&self.density_buffer, self.output_rect(), 0.0, add_scalar)
+    }
 
-        self.img_buffer
-            .save(filepath)
+    // The tone mapped pixels in `output`, with channels in [0, 1]. Supersampled buffers are filtered
+    // down before tone mapping, so the brightness does not depend on the supersampling.
+    fn mapped_pixels(&self, output: Rect, exposure: Option<f64>) -> Vec<(f64, f64, f64)> {
+        let tone_mapper = self.settings.tone_mapper;
+        let brightness = self.brightness;
+        let threads = self.threads();
+        match &self.settings.color_mode {
+            ColorMode::ParticleColor | ColorMode::Quantity { .. } => {
+                let colors =
+                    self.downsample(&self.color_buffer, output, (0.0, 0.0, 0.0), add_color);
+                let exposure = exposure.unwrap_or_else(|| {
+                    let brightest: Vec<f64> = colors
+                        .iter()
+                        .map(|color| color.0.max(color.1).max(color.2))
+                        .collect();
+                    tone_mapper.exposure(&brightest)
+                });
+                parallel_collect(colors.len(), threads, |i| {
+                    let color = colors[i];
+                    (
+                        tone_mapper.map(color.0, brightness, exposure),
+                        tone_mapper.map(color.1, brightness, exposure),
+                        tone_mapper.map(color.2, brightness, exposure),
+                    )
+                })
+            }
+            ColorMode::Density(colormap) => {
+                let densities = self.downsample(&self.density_buffer, output, 0.0, add_scalar);
+                let exposure = exposure.unwrap_or_else(|| tone_mapper.exposure(&densities));
+                parallel_collect(densities.len(), threads, |i| {
+                    colormap.color(tone_mapper.map(densities[i], brightness, exposure))
+                })
+            }
+            ColorMode::MassWeighted { colormap, .. } => {
+                let densities = self.downsample(&self.density_buffer, output, 0.0, add_scalar);
+                let quantities = self.downsample(&self.quantity_buffer, output, 0.0, add_scalar);
+                let exposure = exposure.unwrap_or_else(|| tone_mapper.exposure(&densities));
+                parallel_collect(densities.len(), threads, |i| {
+                    let density = densities[i];
+                    if density <= 0.0 {
+                        return (0.0, 0.0, 0.0);
+                    }
+                    let color = colormap.color(normalize(quantities[i] / density, self.range));
+                    let value = tone_mapper.map(density, brightness, exposure);
+                    (color.0 * value, color.1 * value, color.2 * value)
+                })
+            }
+        }
+    }
+
+    // 16 bit PNG of the last render, tone mapped like the 8 bit output.
+    pub fn save_png16(&self, filepath: &str) {
+        self.check_whole_frame();
+        let to_u16 = |value: f64| (value * 65535.0) as u16;
+        let data: Vec<u16> = self
+            .mapped_pixels(self.output_rect(), None)
+            .iter()
+            .flat_map(|color| [to_u16(color.0), to_u16(color.1), to_u16(color.2)])
+            .collect();
+        let image = image::ImageBuffer::<image::Rgb<u16>, Vec<u16>>::from_raw(
+            self.width,
+            self.height,
+            data,
+        )
+        .unwrap();
+        image.save(filepath).unwrap();
+    }
+
+    // The raw accumulated colors of the last render as a little endian PFM.
+    pub fn save_pfm(&self, filepath: &str) {
+        let mut data = format!("PF\n{} {}\n-1.0\n", self.width, self.height).into_bytes();
+        // PFM stores the rows bottom to top.
+        for row in self.output_colors().chunks(self.width as usize).rev() {
+            for color in row {
+                for channel in [color.0, color.1, color.2] {
+                    data.extend_from_slice(&(channel as f32).to_le_bytes());
+                }
+            }
+        }
+        fs::write(filepath, data).unwrap();
+    }
+
+    // The raw accumulated colors of the last render as 32 bit float OpenEXR.
+    pub fn save_exr(&self, filepath: &str) {
+        let data: Vec<f32> = self
+            .output_colors()
+            .iter()
+            .flat_map(|color| [color.0 as f32, color.1 as f32, color.2 as f32])
+            .collect();
+        let image = image::Rgb32FImage::from_raw(self.width, self.height, data).unwrap();
+        image
+            .save_with_format(filepath, image::ImageFormat::OpenExr)
             .unwrap();
     }
 
-    fn vector_world_to_screen(&self, vector: Vector2, camera: &Camera) -> Vector2 {
-        let mut new_vector = vector - camera.position;
-        new_vector.y = -new_vector.y;
-        new_vector *= 2f64.powf(camera.zoom) * (self.width as f64 / 2.0);
-        new_vector.x += self.width as f64 / 2.0;
-        new_vector.y += self.height as f64 / 2.0;
+    // The density of the last render (in units of the mean particle mass) as little endian width
+    // and height (u32) followed by the rows top to bottom as f64.
+    pub fn save_density_grid(&self, filepath: &str) {
+        let mut data = vec![];
+        data.extend_from_slice(&self.width.to_le_bytes());
+        data.extend_from_slice(&self.height.to_le_bytes());
+        for density in self.output_densities() {
+            data.extend_from_slice(&density.to_le_bytes());
+        }
+        fs::write(filepath, data).unwrap();
+    }
+
+    // Filters the samples in the buffers down to the output pixels in `output`.
+    fn downsample<T: Copy + Send + Sync>(
+        &self,
+        samples: &[T],
+        output: Rect,
+        zero: T,
+        add: fn(T, T, f64) -> T,
+    ) -> Vec<T> {
+        let factor = (self.sample_width / self.width) as i64;
+        if factor == 1 && self.region == output {
+            return samples.to_vec();
+        }
 
-        new_vector
+        let taps = filter_taps(self.settings.downsample_filter, factor as u32);
+        parallel_collect(output.len(), self.threads(), |i| {
+            let x = output.x + i as i64 % output.width;
+            let y = output.y + i as i64 / output.width;
+            let mut sum = zero;
+            let mut total_weight = 0.0;
+            for (dy, weight_y) in &taps {
+                for (dx, weight_x) in &taps {
+                    let sample_x = x * factor + dx;
+                    let sample_y = y * factor + dy;
+                    if !self.region.contains(sample_x, sample_y) {
+                        continue;
+                    }
+                    let weight = weight_x * weight_y;
+                    sum = add(sum, samples[self.region.index(sample_x, sample_y)], weight);
+                    total_weight += weight;
+                }
+            }
+            add(zero, sum, 1.0 / total_weight)
+        })
+    }
+
+    // Both work in sample pixels, which are output pixels unless supersampling.
+    fn screen_scale(&self, camera: &Camera3D, depth: f64) -> f64 {
+        match camera.projection {
+            Projection::Orthographic => 2f64.powf(camera.zoom) * (self.sample_width as f64 / 2.0),
+            Projection::Perspective { fov } => {
+                self.sample_width as f64 / 2.0 / (fov / 2.0).tan() / depth
+            }
+        }
+    }
+
+    // Screen position, pixels per world unit and depth of a point, or None if it is behind a
+    // perspective camera. `basis` is `camera.basis()`, passed in so it is computed once per frame.
+    fn project(
+        &self,
+        position: Vector3,
+        camera: &Camera3D,
+        (right, up, forward): (Vector3, Vector3, Vector3),
+    ) -> Option<(Vector2, f64, f64)> {
+        let relative = position - camera.position;
+        let depth = relative.dot(forward);
+        if let Projection::Perspective { .. } = camera.projection {
+            if depth <= 0.0 {
+                return None;
+            }
+        }
+
+        let scale = self.screen_scale(camera, depth);
+        let screen_pos = Vector2 {
+            x: relative.dot(right) * scale + self.sample_width as f64 / 2.0,
+            y: -relative.dot(up) * scale + self.sample_height as f64 / 2.0,
+        };
+        Some((screen_pos, scale, depth))
+    }
+}
+
+fn normalize(value: f64, range: (f64, f64)) -> f64 {
+    if range.1 > range.0 {
+        (value - range.0) / (range.1 - range.0)
+    } else {
+        0.5
+    }
+}
+
+fn add_scalar(sum: f64, value: f64, weight: f64) -> f64 {
+    sum + value * weight
+}
+
+fn add_color(sum: (f64, f64, f64), color: (f64, f64, f64), weight: f64) -> (f64, f64, f64) {
+    (
+        sum.0 + color.0 * weight,
+        sum.1 + color.1 * weight,
+        sum.2 + color.2 * weight,
+    )
+}
+
+// Offsets of the samples from the first sample of a pixel along one axis, with their filter
+// weights. The filters are separable, so the 2D weight is the product of the two axes.
+fn filter_taps(filter: DownsampleFilter, factor: u32) -> Vec<(i64, f64)> {
+    let radius = match filter {
+        DownsampleFilter::Box => 0.5,
+        DownsampleFilter::Tent => 1.0,
+        DownsampleFilter::Gaussian => 1.5,
+    };
+    let factor = factor as f64;
+    let first = ((0.5 - radius) * factor).floor() as i64;
+    let last = ((0.5 + radius) * factor).ceil() as i64;
+    (first..last)
+        .filter_map(|offset| {
+            // Distance from the pixel centre to the sample centre in output pixels.
+            let distance = ((offset as f64 + 0.5) / factor - 0.5).abs();
+            let weight = match filter {
+                DownsampleFilter::Box => (distance < 0.5) as u8 as f64,
+                DownsampleFilter::Tent => (1.0 - distance).max(0.0),
+                DownsampleFilter::Gaussian => {
+                    if distance < 1.5 {
+                        (-2.0 * distance * distance).exp()
+                    } else {
+                        0.0
+                    }
+                }
+            };
+            (weight > 0.0).then_some((offset, weight))
+        })
+        .collect()
+}
+
+// Distance in pixels beyond which the kernel is zero.
+fn kernel_support(kernel: Kernel, smoothing_length: f64) -> f64 {
+    match kernel {
+        Kernel::Gaussian => 1.5 * smoothing_length,
+        Kernel::CubicSpline => smoothing_length,
+    }
+}
+
+// Computes `f` for 0..count, split into one contiguous chunk per thread.
+fn parallel_collect<T: Send, F: Fn(usize) -> T + Sync>(
+    count: usize,
+    threads: usize,
+    f: F,
+) -> Vec<T> {
+    if threads <= 1 {
+        return (0..count).map(f).collect();
+    }
+    let chunk = count.div_ceil(threads);
+    thread::scope(|scope| {
+        let handles: Vec<_> = (0..threads)
+            .map(|i| {
+                let f = &f;
+                scope.spawn(move || {
+                    (i * chunk..((i + 1) * chunk).min(count))
+                        .map(f)
+                        .collect::<Vec<T>>()
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect()
+    })
+}
+
+fn kernel_weight(kernel: Kernel, q: f64) -> f64 {
+    match kernel {
+        Kernel::Gaussian => {
+            if q < 1.5 {
+                (-2.0 * q * q).exp()
+            } else {
+                0.0
+            }
+        }
+        Kernel::CubicSpline => {
+            if q < 0.5 {
+                1.0 - 6.0 * q * q + 6.0 * q * q * q
+            } else if q < 1.0 {
+                2.0 * (1.0 - q).powi(3)
+            } else {
+                0.0
+            }
+        }
+    }
+}
+
+fn smoothing_lengths(world: &World, size: KernelSize) -> Vec<f64> {
+    match size {
+        KernelSize::Fixed(length) => vec![length; world.particles.len()],
+        KernelSize::MassScaled(length) => world
+            .particles
+            .iter()
+            .map(|particle| length * particle.mass.sqrt())
+            .collect(),
+        KernelSize::Adaptive { neighbours, scale } => {
+            let tree = KdTree::from_world(world);
+            world
+                .particles
+                .iter()
+                .map(|particle| {
+                    // The particle finds itself first, so ask for one more.
+                    let nearest = tree.nearest(particle.position, neighbours + 1);
+                    scale * nearest.last().map_or(0.0, |(_, distance)| *distance)
+                })
+                .collect()
+        }
     }
 }
@@ -0,0 +1,392 @@
+use super::*;
+use rand::Rng;
+use std::f64::consts::PI;
+
+#[derive(Clone, Copy, Debug)]
+pub enum SphericalModel {
+    Plummer {
+        scale_radius: f64,
+    },
+    Hernquist {
+        scale_radius: f64,
+    },
+    // Exact NFW inside the truncation radius, then an exponential decay over a tenth of it.
+    Nfw {
+        scale_radius: f64,
+        truncation_radius: f64,
+    },
+    // `w0` is the dimensionless central potential Ψ(0)/σ².
+    King {
+        core_radius: f64,
+        w0: f64,
+    },
+    Jaffe {
+        scale_radius: f64,
+    },
+}
+
+const TABLE_SIZE: usize = 2000;
+
+struct SphericalProfile {
+    radius: Vec<f64>,
+    mass: Vec<f64>,
+    potential: Vec<f64>,
+}
+
+enum DistributionFunction {
+    Plummer,
+    Hernquist { scale_energy: f64 },
+    King { sigma_squared: f64 },
+    Tabulated { energy: Vec<f64>, value: Vec<f64> },
+}
+
+impl DistributionFunction {
+    fn value(&self, energy: f64) -> f64 {
+        if energy <= 0.0 {
+            return 0.0;
+        }
+        match self {
+            DistributionFunction::Plummer => energy.powf(3.5),
+            DistributionFunction::Hernquist { scale_energy } => {
+                let q = (energy / scale_energy).sqrt().min(1.0 - 1e-9);
+                let q2 = q * q;
+                (3.0 * q.asin()
+                    + q * (1.0 - q2).sqrt() * (1.0 - 2.0 * q2) * (8.0 * q2 * q2 - 8.0 * q2 - 3.0))
+                    / (1.0 - q2).powf(2.5)
+            }
+            DistributionFunction::King { sigma_squared } => (energy / sigma_squared).exp_m1(),
+            DistributionFunction::Tabulated {
+                energy: energies,
+                value,
+            } => interpolate(energies, value, energy).max(0.0),
+        }
+    }
+}
+
+impl SphericalModel {
+    fn profile(
+        &self,
+        mass: f64,
+        gravity_strength: f64,
+    ) -> (SphericalProfile, DistributionFunction) {
+        let gm = gravity_strength * mass;
+        match *self {
+            SphericalModel::Plummer { scale_radius: a } => {
+                let radius = log_grid(a * 1e-4, a * 1e4);
+                let profile = SphericalProfile {
+                    mass: radius
+                        .iter()
+                        .map(|r| r.powi(3) / (r * r + a * a).powf(1.5))
+                        .collect(),
+                    potential: radius.iter().map(|r| gm / (r * r + a * a).sqrt()).collect(),
+                    radius,
+                };
+                (profile, DistributionFunction::Plummer)
+            }
+            SphericalModel::Hernquist { scale_radius: a } => {
+                let radius = log_grid(a * 1e-4, a * 1e4);
+                let profile = SphericalProfile {
+                    mass: radius.iter().map(|r| (r / (r + a)).powi(2)).collect(),
+                    potential: radius.iter().map(|r| gm / (r + a)).collect(),
+                    radius,
+                };
+                let distribution = DistributionFunction::Hernquist {
+                    scale_energy: gm / a,
+                };
+                (profile, distribution)
+            }
+            SphericalModel::Jaffe { scale_radius: a } => {
+                let radius = log_grid(a * 1e-4, a * 1e4);
+                let density: Vec<f64> = radius
+                    .iter()
+                    .map(|r| mass * a / (4.0 * PI * r * r * (r + a).powi(2)))
+                    .collect();
+                let profile = SphericalProfile {
+                    mass: radius.iter().map(|r| r / (r + a)).collect(),
+                    potential: radius.iter().map(|r| gm / a * (1.0 + a / r).ln()).collect(),
+                    radius,
+                };
+                let distribution = eddington(&profile.potential, &density);
+                (profile, distribution)
+            }
+            SphericalModel::Nfw {
+                scale_radius: a,
+                truncation_radius: rt,
+            } => {
+                let decay = rt / 10.0;
+                let c = rt / a;
+                let slope = -(1.0 + 3.0 * c) / (1.0 + c) + rt / decay;
+                let nfw = |r: f64| 1.0 / (r / a * (1.0 + r / a).powi(2));
+                let radius = log_grid(a * 1e-4, rt + 40.0 * decay);
+                let density: Vec<f64> = radius
+                    .iter()
+                    .map(|&r| {
+                        if r <= rt {
+                            nfw(r)
+                        } else {
+                            nfw(rt) * (r / rt).powf(slope) * (-(r - rt) / decay).exp()
+                        }
+                    })
+                    .collect();
+                let (profile, density) = tabulated_profile(radius, density, mass, gravity_strength);
+                let distribution = eddington(&profile.potential, &density);
+                (profile, distribution)
+            }
+            SphericalModel::King { core_radius, w0 } => king_profile(core_radius, w0, gm),
+        }
+    }
+}
+
+// Samples the sphere with velocities from its distribution function, which is an equilibrium in
+// 3D. `World` integrates in the plane with forces from the projected distances and the velocities
+// along z are dropped, so the result is not an equilibrium in `World` and collapses straight away
+// (2K/|W| is about 0.4). Use `from_spherical_model_planar` for runs.
+pub fn from_spherical_model(
+    model: SphericalModel,
+    num_particles: u32,
+    mass: f64,
+    settings: WorldSettings,
+) -> World {
+    let (profile, distribution) = model.profile(mass, settings.gravity_strength);
+    let total = *profile.mass.last().unwrap();
+    let particle_mass = mass / num_particles as f64;
+
+    let mut world = World::new(settings);
+    let mut rng = rand::thread_rng();
+    for _ in 0..num_particles {
+        let radius = interpolate(&profile.mass, &profile.radius, rng.gen::<f64>() * total);
+        let potential = interpolate(&profile.radius, &profile.potential, radius);
+
+        // Speed from v² f(Ψ - v²/2) by rejection, in units of the escape speed.
+        let weight = |q: f64| q * q * distribution.value(potential * (1.0 - q * q));
+        let mut max_weight = 0.0f64;
+        for i in 1..=100 {
+            max_weight = max_weight.max(weight(i as f64 / 100.0));
+        }
+        max_weight *= 1.1;
+        let mut q = 0.0;
+        for _ in 0..10000 {
+            q = rng.gen::<f64>();
+            if rng.gen::<f64>() * max_weight <= weight(q) {
+                break;
+            }
+        }
+        let speed = q * (2.0 * potential).sqrt();
+
+        let position = isotropic(&mut rng, radius);
+        let velocity = isotropic(&mut rng, speed);
+        world.add_particle(sphere_particle(position, velocity, particle_mass));
+    }
+
+    world
+}
+
+// The sphere projected onto the plane, in equilibrium under the forces `World` integrates. The
+// positions are sampled as in `from_spherical_model`, and the velocities come from the isotropic
+// Jeans equation in the plane with the circular speed from the force calculation in `settings`.
+pub fn from_spherical_model_planar(
+    model: SphericalModel,
+    num_particles: u32,
+    mass: f64,
+    settings: WorldSettings,
+) -> World {
+    let (profile, _) = model.profile(mass, settings.gravity_strength);
+    let total = *profile.mass.last().unwrap();
+    let particle_mass = mass / num_particles as f64;
+
+    let mut world = World::new(settings);
+    let mut rng = rand::thread_rng();
+    for _ in 0..num_particles {
+        let radius = interpolate(&profile.mass, &profile.radius, rng.gen::<f64>() * total);
+        let position = isotropic(&mut rng, radius);
+        world.add_particle(sphere_particle(position, (0.0, 0.0, 0.0), particle_mass));
+    }
+
+    // Around the centre of the model, as the centre of mass of a sample of a long tailed model
+    // like Hernquist or Jaffe can be a scale radius away from it.
+    let origin = Vector2 { x: 0.0, y: 0.0 };
+    let settings = EquilibriumSettings {
+        circular_speed: CircularSpeed::Tree,
+        dispersion: Dispersion::Isotropic,
+        num_bins: 50,
+    };
+    world.set_equilibrium_velocity_around(origin, origin, &settings, |_| true);
+    world
+}
+
+// The height is kept in `Particle::z` for rendering, the velocity along z is dropped.
+fn sphere_particle(position: (f64, f64, f64), velocity: (f64, f64, f64), mass: f64) -> Particle {
+    Particle {
+        mass,
+        position: Vector2 {
+            x: position.0,
+            y: position.1,
+        },
+        z: position.2,
+        velocity: Vector2 {
+            x: velocity.0,
+            y: velocity.1,
+        },
+        color: (1.0, 1.0, 1.0),
+        tag: 0,
+        potential: 0.0,
+    }
+}
+
+fn king_profile(core_radius: f64, w0: f64, gm: f64) -> (SphericalProfile, DistributionFunction) {
+    // Poisson's equation in units of the core radius and σ², with p = x² dW/dx, stepped in ln x.
+    let central_density = king_density(w0);
+    let derivatives = |x: f64, w: f64, p: f64| {
+        (
+            p / x,
+            -9.0 * x.powi(3) * king_density(w.max(0.0)) / central_density,
+        )
+    };
+
+    let step: f64 = 1e-3;
+    let mut x: f64 = 1e-4;
+    let mut w = w0 - 1.5 * x * x;
+    let mut p = -3.0 * x.powi(3);
+    let mut table = vec![(x, w, p)];
+    while w > 0.0 {
+        let (k1w, k1p) = derivatives(x, w, p);
+        let x2 = x * (step / 2.0).exp();
+        let (k2w, k2p) = derivatives(x2, w + k1w * step / 2.0, p + k1p * step / 2.0);
+        let (k3w, k3p) = derivatives(x2, w + k2w * step / 2.0, p + k2p * step / 2.0);
+        let x4 = x * step.exp();
+        let (k4w, k4p) = derivatives(x4, w + k3w * step, p + k3p * step);
+        w += (k1w + 2.0 * k2w + 2.0 * k3w + k4w) * step / 6.0;
+        p += (k1p + 2.0 * k2p + 2.0 * k3p + k4p) * step / 6.0;
+        x = x4;
+        table.push((x, w.max(0.0), p));
+    }
+
+    // G M(r) = -σ² r0 p, which fixes σ² from the total mass.
+    let tidal_mass = -table.last().unwrap().2;
+    let sigma_squared = gm / (core_radius * tidal_mass);
+    let profile = SphericalProfile {
+        radius: table.iter().map(|(x, _, _)| x * core_radius).collect(),
+        mass: table.iter().map(|(_, _, p)| -p / tidal_mass).collect(),
+        potential: table.iter().map(|(_, w, _)| w * sigma_squared).collect(),
+    };
+    (profile, DistributionFunction::King { sigma_squared })
+}
+
+// e^W erf(√W) - √(4W/π)(1 + 2W/3) as a series, which avoids the cancellation at small W.
+fn king_density(w: f64) -> f64 {
+    let mut term = 4.0 * w.powf(2.5) / 15.0;
+    let mut sum = 0.0;
+    let mut n = 2.0;
+    while term > sum * 1e-16 {
+        sum += term;
+        term *= 2.0 * w / (2.0 * n + 3.0);
+        n += 1.0;
+    }
+    2.0 / PI.sqrt() * sum
+}
+
+// Integrates an unnormalised density into enclosed mass and relative potential, scaled to `mass`.
+fn tabulated_profile(
+    radius: Vec<f64>,
+    density: Vec<f64>,
+    mass: f64,
+    gravity_strength: f64,
+) -> (SphericalProfile, Vec<f64>) {
+    let n = radius.len();
+    let inner_slope = (density[1] / density[0]).ln() / (radius[1] / radius[0]).ln();
+    let mut enclosed = vec![4.0 * PI * density[0] * radius[0].powi(3) / (3.0 + inner_slope)];
+    for k in 1..n {
+        let shell = 4.0 * PI / 2.0
+            * (density[k - 1] * radius[k - 1].powi(2) + density[k] * radius[k].powi(2))
+            * (radius[k] - radius[k - 1]);
+        enclosed.push(enclosed[k - 1] + shell);
+    }
+    let mut outer = vec![0.0; n];
+    for k in (0..n - 1).rev() {
+        outer[k] = outer[k + 1]
+            + 4.0 * PI / 2.0
+                * (density[k] * radius[k] + density[k + 1] * radius[k + 1])
+                * (radius[k + 1] - radius[k]);
+    }
+
+    let scale = mass / enclosed[n - 1];
+    let potential = (0..n)
+        .map(|k| gravity_strength * scale * (enclosed[k] / radius[k] + outer[k]))
+        .collect();
+    let profile = SphericalProfile {
+        mass: enclosed.iter().map(|m| m * scale).collect(),
+        potential,
+        radius,
+    };
+    (profile, density.iter().map(|rho| rho * scale).collect())
+}
+
+// Eddington inversion, f(E) ∝ d/dE ∫ dρ/dΨ dΨ / √(E - Ψ), with dρ/dΨ linear between table points.
+fn eddington(potential: &[f64], density: &[f64]) -> DistributionFunction {
+    let psi: Vec<f64> = potential.iter().rev().cloned().collect();
+    let rho: Vec<f64> = density.iter().rev().cloned().collect();
+    let n = psi.len();
+
+    let mut slope = vec![];
+    for k in 0..n {
+        let (a, b) = (k.saturating_sub(1), (k + 1).min(n - 1));
+        slope.push((rho[b] - rho[a]) / (psi[b] - psi[a]));
+    }
+
+    let segment = |e: f64, psi_a: f64, psi_b: f64, g_a: f64, g_b: f64| {
+        let beta = (g_b - g_a) / (psi_b - psi_a);
+        let alpha = g_a - beta * psi_a;
+        let s_a = (e - psi_a).max(0.0);
+        let s_b = (e - psi_b).max(0.0);
+        2.0 * (alpha + beta * e) * (s_a.sqrt() - s_b.sqrt())
+            - 2.0 / 3.0 * beta * (s_a.powf(1.5) - s_b.powf(1.5))
+    };
+
+    let mut integral = vec![];
+    for j in 0..n {
+        let e = psi[j];
+        let mut sum = segment(e, 0.0, psi[0], rho[0] / psi[0], rho[0] / psi[0]);
+        for k in 0..j {
+            sum += segment(e, psi[k], psi[k + 1], slope[k], slope[k + 1]);
+        }
+        integral.push(sum);
+    }
+
+    let mut value = vec![];
+    for j in 0..n {
+        let (a, b) = (j.saturating_sub(1), (j + 1).min(n - 1));
+        let derivative = (integral[b] - integral[a]) / (psi[b] - psi[a]);
+        value.push((derivative / (8f64.sqrt() * PI * PI)).max(0.0));
+    }
+    DistributionFunction::Tabulated { energy: psi, value }
+}
+
+fn log_grid(min: f64, max: f64) -> Vec<f64> {
+    (0..TABLE_SIZE)
+        .map(|k| min * (max / min).powf(k as f64 / (TABLE_SIZE - 1) as f64))
+        .collect()
+}
+
+// Linear interpolation of `y` at `x`, where `xs` is ascending, clamped at the ends.
+fn interpolate(xs: &[f64], ys: &[f64], x: f64) -> f64 {
+    let next = xs.partition_point(|value| *value < x);
+    if next == 0 {
+        return ys[0];
+    }
+    if next == xs.len() {
+        return ys[xs.len() - 1];
+    }
+    let t = (x - xs[next - 1]) / (xs[next] - xs[next - 1]);
+    ys[next - 1] + (ys[next] - ys[next - 1]) * t
+}
+
+fn isotropic<R: Rng>(rng: &mut R, length: f64) -> (f64, f64, f64) {
+    let cos_theta = 2.0 * rng.gen::<f64>() - 1.0;
+    let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+    let phi = 2.0 * PI * rng.gen::<f64>();
+    (
+        length * sin_theta * phi.cos(),
+        length * sin_theta * phi.sin(),
+        length * cos_theta,
+    )
+}
@@ -0,0 +1,145 @@
+use super::*;
+use std::fmt::Write;
+use std::fs;
+
+// Vector output for small test systems like two bodies or a solar system, where every particle
+// should be visible as a circle. Uses the same camera mapping as the renderer.
+pub struct SvgWriter {
+    width: u32,
+    height: u32,
+    // Circle radius is `particle_size * sqrt(mass)` in world units, as in the Python prototype.
+    pub particle_size: f64,
+    // Smallest circle radius in pixels, so light particles do not disappear.
+    pub min_radius: f64,
+    pub background: (f64, f64, f64),
+    // Orbit line width in pixels. Zero leaves out the orbits even if positions were recorded.
+    pub orbit_width: f64,
+    orbits: Vec<Vec<Vector2>>,
+}
+
+impl SvgWriter {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            particle_size: 0.05,
+            min_radius: 1.0,
+            background: (0.0, 0.0, 0.0),
+            orbit_width: 1.0,
+            orbits: vec![],
+        }
+    }
+
+    // Adds the current positions to the orbits. Call after every update that should be part of the
+    // orbit lines.
+    pub fn record_orbits(&mut self, world: &World) {
+        if self.orbits.is_empty() {
+            self.orbits = vec![vec![]; world.particles.len()];
+        }
+        if self.orbits.len() != world.particles.len() {
+            panic!(
+                "Recorded orbits of {} particles, but the world has {}",
+                self.orbits.len(),
+                world.particles.len()
+            );
+        }
+        for (orbit, particle) in self.orbits.iter_mut().zip(&world.particles) {
+            orbit.push(particle.position);
+        }
+    }
+
+    pub fn clear_orbits(&mut self) {
+        self.orbits.clear();
+    }
+
+    pub fn save(&self, world: &World, camera: &Camera, filepath: &str) {
+        fs::write(filepath, self.to_svg(world, camera)).unwrap();
+    }
+
+    pub fn to_svg(&self, world: &World, camera: &Camera) -> String {
+        let scale = 2f64.powf(camera.zoom) * (self.width as f64 / 2.0);
+        let to_screen = |position: Vector2| Vector2 {
+            x: (position.x - camera.position.x) * scale + self.width as f64 / 2.0,
+            y: -(position.y - camera.position.y) * scale + self.height as f64 / 2.0,
+        };
+
+        let mut svg = String::new();
+        writeln!(
+            svg,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{1}\" viewBox=\"0 0 {0} {1}\">",
+            self.width, self.height
+        )
+        .unwrap();
+        writeln!(
+            svg,
+            "<rect width=\"100%\" height=\"100%\" fill=\"{}\"/>",
+            hex_color(self.background)
+        )
+        .unwrap();
+
+        if self.orbit_width > 0.0 {
+            writeln!(
+                svg,
+                "<g fill=\"none\" stroke-width=\"{}\" stroke-opacity=\"0.6\" stroke-linejoin=\"round\">",
+                self.orbit_width
+            )
+            .unwrap();
+            for (orbit, particle) in self.orbits.iter().zip(&world.particles) {
+                if orbit.len() < 2 {
+                    continue;
+                }
+                write!(
+                    svg,
+                    "<polyline stroke=\"{}\" points=\"",
+                    hex_color(particle.color)
+                )
+                .unwrap();
+                // Points closer than half a pixel to the last one add nothing but file size.
+                let mut last: Option<Vector2> = None;
+                for (i, &position) in orbit.iter().enumerate() {
+                    let point = to_screen(position);
+                    let is_last = i == orbit.len() - 1;
+                    if let Some(last) = last {
+                        if (point - last).abs() < 0.5 && !is_last {
+                            continue;
+                        }
+                    }
+                    write!(svg, "{:.2},{:.2} ", point.x, point.y).unwrap();
+                    last = Some(point);
+                }
+                svg.pop();
+                writeln!(svg, "\"/>").unwrap();
+            }
+            writeln!(svg, "</g>").unwrap();
+        }
+
+        for particle in &world.particles {
+            let center = to_screen(particle.position);
+            let radius = (self.particle_size * particle.mass.sqrt() * scale).max(self.min_radius);
+            if center.x + radius < 0.0
+                || center.y + radius < 0.0
+                || center.x - radius > self.width as f64
+                || center.y - radius > self.height as f64
+            {
+                continue;
+            }
+            writeln!(
+                svg,
+                "<circle cx=\"{:.2}\" cy=\"{:.2}\" r=\"{:.2}\" fill=\"{}\"/>",
+                center.x,
+                center.y,
+                radius,
+                hex_color(particle.color)
+            )
+            .unwrap();
+        }
+
+        writeln!(svg, "</svg>").unwrap();
+        svg
+    }
+}
+
+fn hex_color(color: (f64, f64, f64)) -> String {
+    let [r, g, b] = to_rgb(color);
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
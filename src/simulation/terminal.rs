@@ -0,0 +1,150 @@
+use super::*;
+use std::io::Write;
+
+#[derive(Clone, Copy, Debug)]
+pub enum TerminalMode {
+    // Upper half blocks with the top pixel as foreground and the bottom pixel as background, so
+    // every character cell shows two pixels in full color.
+    HalfBlocks,
+    // Braille patterns with 2x4 dots per cell. Four times the pixels of half blocks, but only one
+    // color per cell.
+    Braille,
+}
+
+// Draws the rendered world to the terminal with 24-bit ANSI colors, for watching runs over SSH.
+// Renders with an ordinary `Renderer`, so the cameras and settings are the same as for images.
+pub struct TerminalRenderer {
+    columns: u32,
+    rows: u32,
+    mode: TerminalMode,
+    refresh_interval: u64,
+    step: u64,
+    // Energy at the first draw, which the energy error is relative to.
+    initial_energy: Option<f64>,
+    pub renderer: Renderer,
+}
+
+// Pixels with no channel above this leave their braille dot off.
+const BRAILLE_THRESHOLD: u8 = 48;
+
+impl TerminalRenderer {
+    // `columns` and `rows` are character cells. One row is kept for the status line.
+    pub fn new(columns: u32, rows: u32, mode: TerminalMode, settings: RenderSettings) -> Self {
+        let (width, height) = match mode {
+            TerminalMode::HalfBlocks => (columns, 2 * (rows - 1)),
+            TerminalMode::Braille => (2 * columns, 4 * (rows - 1)),
+        };
+        Self {
+            columns,
+            rows,
+            mode,
+            refresh_interval: 1,
+            step: 0,
+            initial_energy: None,
+            renderer: Renderer::with_settings(width, height, settings),
+        }
+    }
+
+    // Only draw every `steps` calls to `step`.
+    pub fn refresh_interval(mut self, steps: u64) -> Self {
+        self.refresh_interval = steps.max(1);
+        self
+    }
+
+    // Call once after every update of the world.
+    pub fn step<C: Into<Camera3D>>(&mut self, world: &World, camera: C) {
+        if self.step.is_multiple_of(self.refresh_interval) {
+            self.draw(world, camera);
+        }
+        self.step += 1;
+    }
+
+    pub fn draw<C: Into<Camera3D>>(&mut self, world: &World, camera: C) {
+        self.renderer.set_frame(self.step);
+        self.renderer.render_frame_3d(world, &camera.into());
+
+        let energy = world.total_energy();
+        let initial_energy = *self.initial_energy.get_or_insert(energy);
+        let energy_error = if initial_energy != 0.0 {
+            (energy - initial_energy) / initial_energy.abs()
+        } else {
+            0.0
+        };
+
+        let mut output = String::new();
+        if self.step == 0 {
+            output.push_str("\x1b[2J");
+        }
+        output.push_str("\x1b[H");
+        match self.mode {
+            TerminalMode::HalfBlocks => self.half_blocks(&mut output),
+            TerminalMode::Braille => self.braille(&mut output),
+        }
+        let status = format!(
+            "t = {:.1}  step {}  dE/E = {:+.3e}",
+            world.time, self.step, energy_error
+        );
+        let status: String = status.chars().take(self.columns as usize).collect();
+        output.push_str(&status);
+        output.push_str("\x1b[K");
+
+        let mut stdout = std::io::stdout().lock();
+        stdout.write_all(output.as_bytes()).unwrap();
+        stdout.flush().unwrap();
+    }
+
+    fn half_blocks(&self, output: &mut String) {
+        let image = self.renderer.image();
+        for row in 0..self.rows - 1 {
+            for column in 0..self.columns {
+                let top = image.get_pixel(column, 2 * row).0;
+                let bottom = image.get_pixel(column, 2 * row + 1).0;
+                output.push_str(&format!(
+                    "\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m\u{2580}",
+                    top[0], top[1], top[2], bottom[0], bottom[1], bottom[2]
+                ));
+            }
+            output.push_str("\x1b[0m\n");
+        }
+    }
+
+    fn braille(&self, output: &mut String) {
+        // Bit of each dot in the Unicode braille block, indexed by [y][x] within the cell.
+        const DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+
+        let image = self.renderer.image();
+        for row in 0..self.rows - 1 {
+            for column in 0..self.columns {
+                let mut dots = 0;
+                let mut color = [0u32; 3];
+                let mut lit = 0;
+                for (y, bits) in DOTS.iter().enumerate() {
+                    for (x, bit) in bits.iter().enumerate() {
+                        let pixel = image
+                            .get_pixel(2 * column + x as u32, 4 * row + y as u32)
+                            .0;
+                        if pixel.iter().any(|&channel| channel >= BRAILLE_THRESHOLD) {
+                            dots |= bit;
+                            lit += 1;
+                            for (sum, channel) in color.iter_mut().zip(pixel) {
+                                *sum += channel as u32;
+                            }
+                        }
+                    }
+                }
+                if lit == 0 {
+                    output.push(' ');
+                    continue;
+                }
+                output.push_str(&format!(
+                    "\x1b[38;2;{};{};{}m{}",
+                    color[0] / lit,
+                    color[1] / lit,
+                    color[2] / lit,
+                    char::from_u32(0x2800 + dots).unwrap()
+                ));
+            }
+            output.push_str("\x1b[0m\n");
+        }
+    }
+}
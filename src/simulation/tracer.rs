@@ -0,0 +1,137 @@
+use super::*;
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+use std::fs;
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct TracePoint {
+    pub time: f64,
+    pub position: Vector2,
+    pub z: f64,
+    pub velocity: Vector2,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Trace {
+    // Index of the particle in `World::particles`.
+    pub index: usize,
+    // Color of the particle when tracing started, used for the path.
+    pub color: (f64, f64, f64),
+    pub points: Vec<TracePoint>,
+}
+
+// Records the trajectories of selected particles, e.g. the black holes through a merger. Particles
+// are followed by index, so the world must not be reordered while tracing.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Tracer {
+    pub traces: Vec<Trace>,
+}
+
+impl Tracer {
+    pub fn new(indices: &[usize]) -> Self {
+        Self {
+            traces: indices
+                .iter()
+                .map(|&index| Trace {
+                    index,
+                    color: (1.0, 1.0, 1.0),
+                    points: vec![],
+                })
+                .collect(),
+        }
+    }
+
+    // Traces every particle with `tag`.
+    pub fn with_tag(world: &World, tag: u32) -> Self {
+        let indices: Vec<usize> = (0..world.particles.len())
+            .filter(|&index| world.particles[index].tag == tag)
+            .collect();
+        Self::new(&indices)
+    }
+
+    // Adds the current state of the traced particles. Call after every update.
+    pub fn record(&mut self, world: &World) {
+        for trace in &mut self.traces {
+            let particle = &world.particles[trace.index];
+            if trace.points.is_empty() {
+                trace.color = particle.color;
+            }
+            trace.points.push(TracePoint {
+                time: world.time,
+                position: particle.position,
+                z: particle.z,
+                velocity: particle.velocity,
+            });
+        }
+    }
+
+    // Draws the paths recorded so far over the last frame rendered by `renderer`, which must have
+    // used `camera`, with a dot at the latest position of each particle.
+    pub fn draw<C: Into<Camera3D>>(&self, renderer: &mut Renderer, camera: C, width: i64) {
+        let camera = camera.into();
+        for trace in &self.traces {
+            let color = to_rgb(trace.color);
+            let points: Vec<Option<Vector2>> = trace
+                .points
+                .iter()
+                .map(|point| {
+                    let position = Vector3 {
+                        x: point.position.x,
+                        y: point.position.y,
+                        z: point.z,
+                    };
+                    renderer.world_to_image(position, &camera)
+                })
+                .collect();
+
+            let image = renderer.image_mut();
+            for pair in points.windows(2) {
+                if let [Some(from), Some(to)] = pair {
+                    draw_line(image, *from, *to, width, color);
+                }
+            }
+            if let Some(Some(last)) = points.last() {
+                let size = 3 * width;
+                fill_rect(
+                    image,
+                    last.x.floor() as i64 - size / 2,
+                    last.y.floor() as i64 - size / 2,
+                    size,
+                    size,
+                    color,
+                );
+            }
+        }
+    }
+
+    // One row per recorded point with the columns `index,time,x,y,z,vx,vy`.
+    pub fn save_csv(&self, path: &str) {
+        let mut csv = String::from("index,time,x,y,z,vx,vy\n");
+        for trace in &self.traces {
+            for point in &trace.points {
+                writeln!(
+                    csv,
+                    "{},{},{},{},{},{},{}",
+                    trace.index,
+                    point.time,
+                    point.position.x,
+                    point.position.y,
+                    point.z,
+                    point.velocity.x,
+                    point.velocity.y
+                )
+                .unwrap();
+            }
+        }
+        fs::write(path, csv).unwrap();
+    }
+
+    // Bincode, like the world snapshots.
+    pub fn save_to_file(&self, path: &str) {
+        fs::write(path, bincode::serialize(self).unwrap()).unwrap();
+    }
+
+    pub fn load_from_file(path: &str) -> Self {
+        bincode::deserialize(&fs::read(path).unwrap()).unwrap()
+    }
+}
@@ -0,0 +1,103 @@
+use super::*;
+use std::fmt::Write;
+use std::fs;
+
+impl World {
+    // The self-bound part of `members`: particles with positive energy in the centre of mass frame
+    // of the rest are removed until none are left to remove. Only the members' own gravity counts,
+    // with potentials from the force calculation in `WorldSettings`. At most a quarter is removed
+    // per iteration, most unbound first, so a large unbound fraction can not drag the centre of
+    // mass velocity away and unbind everything at once.
+    pub fn unbind(&self, members: &[usize]) -> Group {
+        let mut bound = members.to_vec();
+        let mut subset = World::new(self.settings.clone());
+        while bound.len() >= 2 {
+            subset.particles = bound.iter().map(|&i| self.particles[i].clone()).collect();
+            let velocity = subset.center_of_mass_velocity();
+            let potentials = subset.calculate_potentials();
+
+            let mut energies: Vec<(f64, usize)> = bound
+                .iter()
+                .zip(potentials)
+                .map(|(&i, potential)| {
+                    let speed = (self.particles[i].velocity - velocity).abs();
+                    (0.5 * speed * speed + potential, i)
+                })
+                .collect();
+            energies.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+            let unbound = energies.len() - energies.partition_point(|(energy, _)| *energy < 0.0);
+            if unbound == 0 {
+                break;
+            }
+            let keep = energies.len() - unbound.min(energies.len().div_ceil(4));
+            bound = energies[..keep].iter().map(|(_, i)| *i).collect();
+        }
+        if bound.len() < 2 {
+            bound.clear();
+        }
+        bound.sort();
+        self.group(bound)
+    }
+
+    pub fn unbind_selection<F>(&self, selection: F) -> Group
+    where
+        F: Fn(&Particle) -> bool,
+    {
+        let members: Vec<usize> = (0..self.particles.len())
+            .filter(|&i| selection(&self.particles[i]))
+            .collect();
+        self.unbind(&members)
+    }
+}
+
+// Bound mass of a few objects, e.g. the galaxies of a collision, through a run. Every record starts
+// the unbinding from the original members, so particles can become bound again.
+pub struct BoundMassHistory {
+    names: Vec<String>,
+    members: Vec<Vec<usize>>,
+    // Time and the bound mass of every object.
+    pub rows: Vec<(f64, Vec<f64>)>,
+}
+
+impl BoundMassHistory {
+    pub fn new() -> Self {
+        Self {
+            names: vec![],
+            members: vec![],
+            rows: vec![],
+        }
+    }
+
+    pub fn track(mut self, name: &str, members: Vec<usize>) -> Self {
+        self.names.push(name.to_string());
+        self.members.push(members);
+        self
+    }
+
+    pub fn record(&mut self, world: &World) {
+        let masses = self
+            .members
+            .iter()
+            .map(|members| world.unbind(members).mass)
+            .collect();
+        self.rows.push((world.time, masses));
+    }
+
+    // One row per record with the time and a column per tracked object.
+    pub fn save_csv(&self, path: &str) {
+        let mut csv = String::from("time");
+        for name in &self.names {
+            write!(csv, ",{}", name).unwrap();
+        }
+        csv.push('\n');
+        for (time, masses) in &self.rows {
+            write!(csv, "{}", time).unwrap();
+            for mass in masses {
+                write!(csv, ",{}", mass).unwrap();
+            }
+            csv.push('\n');
+        }
+        fs::write(path, csv).unwrap();
+    }
+}
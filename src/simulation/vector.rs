@@ -12,6 +12,22 @@ impl Vector2 {
     pub fn abs(&self) -> f64 {
         (self.x * self.x + self.y * self.y).sqrt()
     }
+
+    pub fn dot(&self, other: Vector2) -> f64 {
+        self.x * other.x + self.y * other.y
+    }
+
+    pub fn cross(&self, other: Vector2) -> f64 {
+        self.x * other.y - self.y * other.x
+    }
+
+    pub fn rotate(&self, angle: f64) -> Vector2 {
+        let (sin, cos) = angle.sin_cos();
+        Vector2 {
+            x: self.x * cos - self.y * sin,
+            y: self.x * sin + self.y * cos,
+        }
+    }
 }
 
 impl ops::Add<Vector2> for Vector2 {
@@ -102,3 +118,108 @@ impl fmt::Display for Vector2 {
         write!(f, "[{}, {}]", self.x, self.y)
     }
 }
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Vector3 {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl Vector3 {
+    pub fn abs(&self) -> f64 {
+        self.dot(*self).sqrt()
+    }
+
+    pub fn dot(&self, other: Vector3) -> f64 {
+        self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    pub fn cross(&self, other: Vector3) -> Vector3 {
+        Vector3 {
+            x: self.y * other.z - self.z * other.y,
+            y: self.z * other.x - self.x * other.z,
+            z: self.x * other.y - self.y * other.x,
+        }
+    }
+
+    pub fn normalize(&self) -> Vector3 {
+        *self / self.abs()
+    }
+}
+
+impl From<Vector2> for Vector3 {
+    fn from(vector: Vector2) -> Self {
+        Self {
+            x: vector.x,
+            y: vector.y,
+            z: 0.0,
+        }
+    }
+}
+
+impl ops::Add<Vector3> for Vector3 {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        Self {
+            x: self.x + rhs.x,
+            y: self.y + rhs.y,
+            z: self.z + rhs.z,
+        }
+    }
+}
+
+impl ops::Sub<Vector3> for Vector3 {
+    type Output = Self;
+
+    fn sub(self, rhs: Vector3) -> Self::Output {
+        Self {
+            x: self.x - rhs.x,
+            y: self.y - rhs.y,
+            z: self.z - rhs.z,
+        }
+    }
+}
+
+impl ops::Mul<f64> for Vector3 {
+    type Output = Vector3;
+
+    fn mul(self, rhs: f64) -> Self::Output {
+        Self {
+            x: self.x * rhs,
+            y: self.y * rhs,
+            z: self.z * rhs,
+        }
+    }
+}
+
+impl ops::Div<f64> for Vector3 {
+    type Output = Vector3;
+
+    fn div(self, rhs: f64) -> Self::Output {
+        Self {
+            x: self.x / rhs,
+            y: self.y / rhs,
+            z: self.z / rhs,
+        }
+    }
+}
+
+impl ops::Neg for Vector3 {
+    type Output = Vector3;
+
+    fn neg(self) -> Self::Output {
+        Self {
+            x: -self.x,
+            y: -self.y,
+            z: -self.z,
+        }
+    }
+}
+
+impl fmt::Display for Vector3 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}, {}, {}]", self.x, self.y, self.z)
+    }
+}
@@ -0,0 +1,244 @@
+use image::codecs::{gif, jpeg};
+use std::fs;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+
+// Streams rendered frames into a video file, so runs do not need ffmpeg or a directory of PNGs.
+// The container is chosen from the extension: `.y4m` (uncompressed YUV 4:4:4), `.gif` or `.avi`
+// (Motion JPEG).
+pub struct VideoWriter {
+    width: u32,
+    height: u32,
+    frame_rate: u32,
+    frames: u32,
+    encoder: Encoder,
+}
+
+enum Encoder {
+    Y4m(BufWriter<fs::File>),
+    Gif(gif::GifEncoder<BufWriter<fs::File>>),
+    Mjpeg {
+        file: BufWriter<fs::File>,
+        quality: u8,
+        // Offset from the `movi` list type and size of every frame chunk, for the index.
+        chunks: Vec<(u32, u32)>,
+        movi_size: u32,
+    },
+}
+
+// Size of everything in the AVI file before the first frame chunk.
+const AVI_HEADER_SIZE: u32 = 224;
+
+impl VideoWriter {
+    pub fn new(filepath: &str, width: u32, height: u32, frame_rate: u32) -> Self {
+        let mut file = BufWriter::new(fs::File::create(filepath).unwrap());
+        let extension = filepath.rsplit('.').next().unwrap().to_lowercase();
+        let encoder = match extension.as_str() {
+            "y4m" => {
+                writeln!(
+                    file,
+                    "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444",
+                    width, height, frame_rate
+                )
+                .unwrap();
+                Encoder::Y4m(file)
+            }
+            "gif" => {
+                // Speed 10 of 30 is much faster than the default 1 and still looks fine.
+                let mut encoder = gif::GifEncoder::new_with_speed(file, 10);
+                encoder.set_repeat(gif::Repeat::Infinite).unwrap();
+                Encoder::Gif(encoder)
+            }
+            "avi" => {
+                // The header is rewritten with the real sizes in `finish`.
+                file.write_all(&avi_header(width, height, frame_rate, 0, 0))
+                    .unwrap();
+                Encoder::Mjpeg {
+                    file,
+                    quality: 90,
+                    chunks: vec![],
+                    movi_size: 4,
+                }
+            }
+            _ => panic!("Unsupported video format '{}'", extension),
+        };
+
+        Self {
+            width,
+            height,
+            frame_rate,
+            frames: 0,
+            encoder,
+        }
+    }
+
+    pub fn write_frame(&mut self, image: &image::RgbImage) {
+        if image.width() != self.width || image.height() != self.height {
+            panic!(
+                "Frame is {}x{}, but the video is {}x{}",
+                image.width(),
+                image.height(),
+                self.width,
+                self.height
+            );
+        }
+
+        match &mut self.encoder {
+            Encoder::Y4m(file) => {
+                let mut planes = vec![0u8; image.len()];
+                let plane_size = image.len() / 3;
+                for (i, pixel) in image.pixels().enumerate() {
+                    let (y, u, v) = rgb_to_yuv(pixel.0);
+                    planes[i] = y;
+                    planes[plane_size + i] = u;
+                    planes[2 * plane_size + i] = v;
+                }
+                file.write_all(b"FRAME\n").unwrap();
+                file.write_all(&planes).unwrap();
+            }
+            Encoder::Gif(encoder) => {
+                let rgba = image::DynamicImage::ImageRgb8(image.clone()).into_rgba8();
+                let delay = image::Delay::from_numer_denom_ms(1000, self.frame_rate);
+                encoder
+                    .encode_frame(image::Frame::from_parts(rgba, 0, 0, delay))
+                    .unwrap();
+            }
+            Encoder::Mjpeg {
+                file,
+                quality,
+                chunks,
+                movi_size,
+            } => {
+                let mut data = vec![];
+                jpeg::JpegEncoder::new_with_quality(&mut data, *quality)
+                    .encode(image, self.width, self.height, image::ColorType::Rgb8)
+                    .unwrap();
+                let size = data.len() as u32;
+                if size % 2 == 1 {
+                    data.push(0);
+                }
+
+                chunks.push((*movi_size, size));
+                file.write_all(b"00dc").unwrap();
+                file.write_all(&size.to_le_bytes()).unwrap();
+                file.write_all(&data).unwrap();
+                *movi_size += 8 + data.len() as u32;
+            }
+        }
+        self.frames += 1;
+    }
+
+    // Flushes the file. For AVI this also writes the index and the frame count, so a video that is
+    // never finished can not be played.
+    pub fn finish(self) {
+        match self.encoder {
+            Encoder::Y4m(mut file) => file.flush().unwrap(),
+            Encoder::Gif(encoder) => drop(encoder),
+            Encoder::Mjpeg {
+                mut file,
+                chunks,
+                movi_size,
+                ..
+            } => {
+                file.write_all(b"idx1").unwrap();
+                file.write_all(&(chunks.len() as u32 * 16).to_le_bytes())
+                    .unwrap();
+                for (offset, size) in chunks {
+                    file.write_all(b"00dc").unwrap();
+                    // AVIIF_KEYFRAME, every JPEG frame stands on its own.
+                    file.write_all(&0x10u32.to_le_bytes()).unwrap();
+                    file.write_all(&offset.to_le_bytes()).unwrap();
+                    file.write_all(&size.to_le_bytes()).unwrap();
+                }
+
+                file.seek(SeekFrom::Start(0)).unwrap();
+                file.write_all(&avi_header(
+                    self.width,
+                    self.height,
+                    self.frame_rate,
+                    self.frames,
+                    movi_size,
+                ))
+                .unwrap();
+                file.flush().unwrap();
+            }
+        }
+    }
+}
+
+// Studio range BT.601, which is what players assume for Y4M.
+fn rgb_to_yuv([r, g, b]: [u8; 3]) -> (u8, u8, u8) {
+    let (r, g, b) = (r as f64, g as f64, b as f64);
+    let y = 16.0 + (65.481 * r + 128.553 * g + 24.966 * b) / 255.0;
+    let u = 128.0 + (-37.797 * r - 74.203 * g + 112.0 * b) / 255.0;
+    let v = 128.0 + (112.0 * r - 93.786 * g - 18.214 * b) / 255.0;
+    (y.round() as u8, u.round() as u8, v.round() as u8)
+}
+
+// RIFF header, `hdrl` list and the start of the `movi` list of an AVI file with one MJPEG stream.
+fn avi_header(width: u32, height: u32, frame_rate: u32, frames: u32, movi_size: u32) -> Vec<u8> {
+    let mut header = Vec::with_capacity(AVI_HEADER_SIZE as usize);
+    let u32_le = |header: &mut Vec<u8>, value: u32| header.extend(value.to_le_bytes());
+    let index_size = 8 + frames * 16;
+    let frame_size = width * height * 3;
+
+    header.extend(b"RIFF");
+    u32_le(&mut header, AVI_HEADER_SIZE - 12 + movi_size + index_size);
+    header.extend(b"AVI ");
+
+    header.extend(b"LIST");
+    u32_le(&mut header, 192);
+    header.extend(b"hdrl");
+
+    header.extend(b"avih");
+    u32_le(&mut header, 56);
+    u32_le(&mut header, 1_000_000 / frame_rate);
+    u32_le(&mut header, frame_size * frame_rate);
+    u32_le(&mut header, 0);
+    u32_le(&mut header, 0x10); // AVIF_HASINDEX
+    u32_le(&mut header, frames);
+    u32_le(&mut header, 0);
+    u32_le(&mut header, 1);
+    u32_le(&mut header, frame_size);
+    u32_le(&mut header, width);
+    u32_le(&mut header, height);
+    header.extend([0; 16]);
+
+    header.extend(b"LIST");
+    u32_le(&mut header, 116);
+    header.extend(b"strl");
+
+    header.extend(b"strh");
+    u32_le(&mut header, 56);
+    header.extend(b"vids");
+    header.extend(b"MJPG");
+    u32_le(&mut header, 0);
+    u32_le(&mut header, 0); // Priority and language
+    u32_le(&mut header, 0);
+    u32_le(&mut header, 1);
+    u32_le(&mut header, frame_rate);
+    u32_le(&mut header, 0);
+    u32_le(&mut header, frames);
+    u32_le(&mut header, frame_size);
+    u32_le(&mut header, u32::MAX);
+    u32_le(&mut header, 0);
+    header.extend([0, 0, 0, 0]);
+    header.extend((width as u16).to_le_bytes());
+    header.extend((height as u16).to_le_bytes());
+
+    header.extend(b"strf");
+    u32_le(&mut header, 40);
+    u32_le(&mut header, 40);
+    u32_le(&mut header, width);
+    u32_le(&mut header, height);
+    header.extend(1u16.to_le_bytes());
+    header.extend(24u16.to_le_bytes());
+    header.extend(b"MJPG");
+    u32_le(&mut header, frame_size);
+    header.extend([0; 16]);
+
+    header.extend(b"LIST");
+    u32_le(&mut header, movi_size);
+    header.extend(b"movi");
+
+    header
+}
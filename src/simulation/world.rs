@@ -9,6 +9,9 @@ use std::fs;
 pub struct World {
     pub particles: Vec<Particle>,
     pub settings: WorldSettings,
+    // Simulation time advanced by `update` and stored in snapshots. Snapshots from before it was
+    // stored load with 0.
+    pub time: f64,
 }
 
 impl World {
@@ -16,6 +19,7 @@ impl World {
         Self {
             particles: vec![],
             settings,
+            time: 0.0,
         }
     }
 
@@ -37,16 +41,22 @@ impl World {
             self.add_particle(Particle {
                 mass: mass / num_particles as f64 / 2.0,
                 position,
+                z: 0.0,
                 velocity: Vector2 { x: 0.0, y: 0.0 },
                 color,
+                tag: 0,
+                potential: 0.0,
             });
         }
 
         self.add_particle(Particle {
             mass: mass / 2.0,
             position: Vector2 { x: 0.0, y: 0.0 },
+            z: 0.0,
             velocity: Vector2 { x: 0.0, y: 0.0 },
             color,
+            tag: 0,
+            potential: 0.0,
         });
 
         self.set_circle_speed(false);
@@ -70,8 +80,11 @@ impl World {
             self.add_particle(Particle {
                 mass: mass / num_particles as f64 / 2.0,
                 position,
+                z: 0.0,
                 velocity: Vector2 { x: 0.0, y: 0.0 },
                 color,
+                tag: 0,
+                potential: 0.0,
             });
         }
 
@@ -80,19 +93,21 @@ impl World {
 
     pub fn set_circle_speed(&mut self, softening: bool) {
         let forces = self.calculate_forces_auto();
+        let center = self.center_of_mass();
+        let center_velocity = self.center_of_mass_velocity();
         let mut start_velocities: Vec<Vector2> = vec![];
         for (particle, force) in iter::zip(&self.particles, forces) {
-            if particle.position.x == 0.0 && particle.position.y == 0.0 {
-                //println!("KULT");
-                start_velocities.push(Vector2 { x: 0.0, y: 0.0 });
+            let offset = particle.position - center;
+            let distance = offset.abs();
+            if distance == 0.0 {
+                start_velocities.push(center_velocity);
                 continue;
             }
             let acceleration = force / particle.mass;
-            let mut velocity = (acceleration.abs() * particle.position.abs()).sqrt();
-            let vector_to_center = (-particle.position) / particle.position.abs();
+            let mut velocity = (acceleration.abs() * distance).sqrt();
+            let vector_to_center = (-offset) / distance;
 
             if softening {
-                let distance = particle.position.abs();
                 let a = 0.4;
                 let proportion = distance / (distance + a);
                 velocity *= proportion;
@@ -103,12 +118,11 @@ impl World {
                 y: -vector_to_center.x,
             } * velocity;
 
-            start_velocities.push(velocity_vector);
+            start_velocities.push(velocity_vector + center_velocity);
         }
 
         for (particle, velocity) in iter::zip(&mut self.particles, start_velocities) {
             particle.velocity = velocity;
-            //println!("{}", particle.velocity)
         }
     }
 
@@ -133,7 +147,61 @@ impl World {
         gravity
     }
 
-    fn calculate_forces_auto(&self) -> Vec<Vector2> {
+    pub fn calculate_potential(&self, position: Vector2) -> f64 {
+        let mut potential = 0.0;
+        for particle in &self.particles {
+            if particle.position.x == position.x && particle.position.y == position.y {
+                continue;
+            }
+            let distance = (particle.position - position).abs();
+            potential += softened_potential(particle.mass, distance, &self.settings);
+        }
+        potential
+    }
+
+    // Potential per unit mass at every particle, with the same tree and opening criterion as the
+    // forces when `quadtree` is set, and spread over all cores when `multiprocessing` is set.
+    pub fn calculate_potentials(&self) -> Vec<f64> {
+        let quadtree = if self.settings.quadtree && !self.particles.is_empty() {
+            Some(self.construct_quadtree())
+        } else {
+            None
+        };
+        let potential = |particle: &Particle| match &quadtree {
+            Some(quadtree) => quadtree.calculate_potential(particle.position, 0, &self.settings),
+            None => self.calculate_potential(particle.position),
+        };
+        if !self.settings.multiprocessing {
+            return self.particles.iter().map(potential).collect();
+        }
+
+        let num_threads = thread::available_parallelism().unwrap().get();
+        let chunk_size = self.particles.len().div_ceil(num_threads).max(1);
+        thread::scope(|scope| {
+            let handles: Vec<_> = self
+                .particles
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    let potential = &potential;
+                    scope.spawn(move || chunk.iter().map(potential).collect::<Vec<f64>>())
+                })
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().unwrap())
+                .collect()
+        })
+    }
+
+    // Stores `calculate_potentials` in the particles, e.g. right before saving a snapshot.
+    pub fn update_potentials(&mut self) {
+        let potentials = self.calculate_potentials();
+        for (particle, potential) in iter::zip(&mut self.particles, potentials) {
+            particle.potential = potential;
+        }
+    }
+
+    pub(super) fn calculate_forces_auto(&self) -> Vec<Vector2> {
         let start_time = time::Instant::now();
         let forces = match (self.settings.multiprocessing, self.settings.quadtree) {
             (false, false) => self.calculate_forces(),
@@ -285,6 +353,7 @@ impl World {
             //println!("{}: {}", i, particle.position);
             i += 1;
         }
+        self.time += delta_time;
     }
 
     pub fn add_position(&mut self, position: Vector2) {
@@ -299,6 +368,65 @@ impl World {
         }
     }
 
+    pub fn total_mass(&self) -> f64 {
+        self.particles.iter().map(|particle| particle.mass).sum()
+    }
+
+    pub fn center_of_mass(&self) -> Vector2 {
+        let total_mass = self.total_mass();
+        if total_mass == 0.0 {
+            return Vector2 { x: 0.0, y: 0.0 };
+        }
+        let mut center = Vector2 { x: 0.0, y: 0.0 };
+        for particle in &self.particles {
+            center += particle.position * particle.mass;
+        }
+        center / total_mass
+    }
+
+    pub fn center_of_mass_velocity(&self) -> Vector2 {
+        let total_mass = self.total_mass();
+        if total_mass == 0.0 {
+            return Vector2 { x: 0.0, y: 0.0 };
+        }
+        let mut velocity = Vector2 { x: 0.0, y: 0.0 };
+        for particle in &self.particles {
+            velocity += particle.velocity * particle.mass;
+        }
+        velocity / total_mass
+    }
+
+    // Kinetic plus potential energy. Every pair is counted twice in the potentials, hence the half.
+    pub fn total_energy(&self) -> f64 {
+        let potentials = self.calculate_potentials();
+        iter::zip(&self.particles, potentials)
+            .map(|(particle, potential)| {
+                let speed = particle.velocity.abs();
+                particle.mass * (0.5 * speed * speed + 0.5 * potential)
+            })
+            .sum()
+    }
+
+    // Moves the world into its centre of mass frame, with the centre at the origin and at rest.
+    pub fn recenter(&mut self) {
+        let center = self.center_of_mass();
+        let center_velocity = self.center_of_mass_velocity();
+        self.add_position(-center);
+        self.add_velocity(-center_velocity);
+    }
+
+    // Rotates positions around the centre of mass and velocities around the centre of mass
+    // velocity, so the bulk motion of the world is left unchanged.
+    pub fn rotate(&mut self, angle: f64) {
+        let center = self.center_of_mass();
+        let center_velocity = self.center_of_mass_velocity();
+        for particle in &mut self.particles {
+            particle.position = (particle.position - center).rotate(angle) + center;
+            particle.velocity =
+                (particle.velocity - center_velocity).rotate(angle) + center_velocity;
+        }
+    }
+
     pub fn add_world(&mut self, other: &Self) {
         for particle in &other.particles {
             self.add_particle(particle.clone());
@@ -311,9 +439,24 @@ impl World {
         }
     }
 
+    pub fn set_tag(&mut self, tag: u32) {
+        for particle in &mut self.particles {
+            particle.tag = tag;
+        }
+    }
+
+    // Snapshots start with `SNAPSHOT_MAGIC` and a format version. Files without them are the bare
+    // particle lists written before, and are still read.
     pub fn save_to_file(&self, path: &str) {
-        let encoded = bincode::serialize(&self.particles).unwrap();
-        let mut file = fs::OpenOptions::new().write(true).create(true).open(path).unwrap();
+        let mut encoded = SNAPSHOT_MAGIC.to_vec();
+        encoded.extend(SNAPSHOT_VERSION.to_le_bytes());
+        encoded.extend(bincode::serialize(&(self.time, &self.particles)).unwrap());
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .unwrap();
         file.write_all(&encoded).unwrap();
     }
 
@@ -321,8 +464,34 @@ impl World {
         let mut file = fs::OpenOptions::new().read(true).open(path).unwrap();
         let mut encoded = vec![];
         let _ = file.read_to_end(&mut encoded).unwrap();
-        self.particles = bincode::deserialize(&encoded).unwrap();
+        if !encoded.starts_with(SNAPSHOT_MAGIC) {
+            self.particles = legacy_particles(&encoded);
+            self.time = 0.0;
+            return;
+        }
+
+        let body = &encoded[SNAPSHOT_MAGIC.len()..];
+        let version = u32::from_le_bytes(body[..4].try_into().unwrap());
+        if version != SNAPSHOT_VERSION {
+            panic!("{}: unsupported snapshot version {}", path, version);
+        }
+        (self.time, self.particles) = bincode::deserialize(&body[4..]).unwrap();
+    }
+}
+
+const SNAPSHOT_MAGIC: &[u8] = b"PSIMSNAP";
+// The time followed by the particles.
+const SNAPSHOT_VERSION: u32 = 1;
+
+// Potential of a particle under the softened force G m / (d² + ε²), so that energies are
+// consistent with the forces used in `update`.
+fn softened_potential(mass: f64, distance: f64, settings: &WorldSettings) -> f64 {
+    let softening = settings.softening_length;
+    if softening == 0.0 {
+        return -settings.gravity_strength * mass / distance;
     }
+    -settings.gravity_strength * mass / softening
+        * (std::f64::consts::FRAC_PI_2 - (distance / softening).atan())
 }
 
 #[derive(Clone)]
@@ -489,4 +658,29 @@ impl Quadtree {
             gravity
         }
     }
+
+    fn calculate_potential(&self, position: Vector2, node: usize, settings: &WorldSettings) -> f64 {
+        let current_node = &self.nodes[node];
+        let distance = (position - current_node.position).abs().sqrt();
+        let width = current_node.max.x - current_node.min.x;
+        let height = current_node.max.y - current_node.min.y;
+        let size = width.max(height);
+
+        let far_away = size / distance < settings.accuracy;
+        let has_children = current_node.children.is_some();
+        let inside = current_node.inside(position);
+
+        if inside && !has_children {
+            0.0
+        } else if (inside || !far_away) && has_children {
+            let mut potential = 0.0;
+            for child in current_node.children.unwrap() {
+                potential += self.calculate_potential(position, child, settings);
+            }
+            potential
+        } else {
+            let distance = (current_node.position - position).abs();
+            softened_potential(current_node.mass, distance, settings)
+        }
+    }
 }